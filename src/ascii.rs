@@ -0,0 +1,218 @@
+use image::{Rgba, RgbaImage};
+
+use crate::cells::{Cell, CellEncoder};
+use crate::dither::luminance;
+
+/// Minimum gradient magnitude (in luminance per cell) for a cell to be drawn
+/// as an edge.
+const EDGE_THRESHOLD: f32 = 96.0;
+
+/// Encodes 2x4 pixels per cell as characters of a ramp, picked by the
+/// average luminance of the cell's pixels inside the image. Transparent
+/// pixels count as black.
+///
+/// In edge mode cells on a strong luminance edge are drawn as `/ \ | _ -`
+/// instead, depending on the direction of the edge.
+#[derive(Debug, Clone)]
+pub struct AsciiEncoder<'a> {
+    ramp: &'a [char],
+    edges: bool,
+    columns: usize,
+    rows: usize,
+    /// Average luminance (0 to 255) per cell, row by row.
+    luminance: Vec<f32>,
+}
+
+impl<'a> AsciiEncoder<'a> {
+    pub const CELL_WIDTH: u32 = 2;
+    pub const CELL_HEIGHT: u32 = 4;
+
+    pub fn new(image: &RgbaImage, alpha_threshold: u8, ramp: &'a [char], edges: bool) -> Self {
+        let columns = image.width().div_ceil(Self::CELL_WIDTH) as usize;
+        let rows = image.height().div_ceil(Self::CELL_HEIGHT) as usize;
+        let mut sums = vec![0.0f32; columns * rows];
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if pixel[3] >= alpha_threshold {
+                let index = (y / Self::CELL_HEIGHT) as usize * columns + (x / Self::CELL_WIDTH) as usize;
+                sums[index] += luminance(*pixel);
+            }
+        }
+
+        // Cells at the right and bottom edge may be cut off by the image.
+        for (index, sum) in sums.iter_mut().enumerate() {
+            let x = (index % columns) as u32 * Self::CELL_WIDTH;
+            let y = (index / columns) as u32 * Self::CELL_HEIGHT;
+            let width  = (image.width()  - x).min(Self::CELL_WIDTH);
+            let height = (image.height() - y).min(Self::CELL_HEIGHT);
+            *sum /= (width * height) as f32;
+        }
+
+        Self { ramp, edges, columns, rows, luminance: sums }
+    }
+
+    #[inline]
+    fn luminance_at(&self, x: i64, y: i64) -> f32 {
+        let x = x.clamp(0, self.columns as i64 - 1) as usize;
+        let y = y.clamp(0, self.rows as i64 - 1) as usize;
+        self.luminance[y * self.columns + x]
+    }
+
+    /// Picks an edge glyph for the cell from the Sobel gradient of the cell
+    /// luminances, or `None` if the gradient is too weak.
+    fn edge_glyph(&self, x: u32, y: u32) -> Option<char> {
+        let (x, y) = (x as i64, y as i64);
+        let l = |dx: i64, dy: i64| self.luminance_at(x + dx, y + dy);
+
+        let gx = (l(1, -1) + 2.0 * l(1, 0) + l(1, 1)) - (l(-1, -1) + 2.0 * l(-1, 0) + l(-1, 1));
+        let gy = (l(-1, 1) + 2.0 * l(0, 1) + l(1, 1)) - (l(-1, -1) + 2.0 * l(0, -1) + l(1, -1));
+
+        if (gx * gx + gy * gy).sqrt() / 4.0 < EDGE_THRESHOLD {
+            return None;
+        }
+
+        // Cells are twice as high as wide, so scale to pixel units to get
+        // the angle of the edge as it appears on screen.
+        let gx = gx / Self::CELL_WIDTH as f32;
+        let gy = gy / Self::CELL_HEIGHT as f32;
+
+        // The edge runs perpendicular to the gradient.
+        let angle = gy.atan2(gx).to_degrees().rem_euclid(180.0);
+        let glyph = if !(22.5..157.5).contains(&angle) {
+            '|'
+        } else if angle < 67.5 {
+            '/'
+        } else if angle < 112.5 {
+            // Put the line at the bottom of the cell if that is where the
+            // luminance jumps.
+            if (l(0, 1) - l(0, 0)).abs() > (l(0, 0) - l(0, -1)).abs() { '_' } else { '-' }
+        } else {
+            '\\'
+        };
+
+        Some(glyph)
+    }
+}
+
+impl CellEncoder for AsciiEncoder<'_> {
+    #[inline]
+    fn cell_size(&self) -> (u32, u32) {
+        (Self::CELL_WIDTH, Self::CELL_HEIGHT)
+    }
+
+    #[inline]
+    fn context(&self) -> u32 {
+        if self.edges { 1 } else { 0 }
+    }
+
+    fn encode_cell(&self, image: &RgbaImage, x: u32, y: u32, alpha_threshold: u8) -> Cell {
+        let mut count = 0;
+        let mut sum = [0u32; 3];
+
+        for py in y * Self::CELL_HEIGHT..((y + 1) * Self::CELL_HEIGHT).min(image.height()) {
+            for px in x * Self::CELL_WIDTH..((x + 1) * Self::CELL_WIDTH).min(image.width()) {
+                let Rgba([r, g, b, a]) = *image.get_pixel(px, py);
+                if a >= alpha_threshold {
+                    count += 1;
+                    sum[0] += r as u32;
+                    sum[1] += g as u32;
+                    sum[2] += b as u32;
+                }
+            }
+        }
+
+        if count == 0 {
+            return Cell::EMPTY;
+        }
+
+        let ch = self.edges.then(|| self.edge_glyph(x, y)).flatten().unwrap_or_else(|| {
+            let luminance = self.luminance[y as usize * self.columns + x as usize];
+            let index = (luminance / 256.0 * self.ramp.len() as f32) as usize;
+            self.ramp[index.min(self.ramp.len() - 1)]
+        });
+
+        if ch == ' ' {
+            return Cell::EMPTY;
+        }
+
+        let half = count / 2;
+        let color = Rgba([
+            ((sum[0] + half) / count) as u8,
+            ((sum[1] + half) / count) as u8,
+            ((sum[2] + half) / count) as u8,
+            255,
+        ]);
+
+        Cell { ch, fg: Some(color), bg: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAMP: [char; 4] = [' ', '.', 'o', '@'];
+
+    fn glyphs(encoder: &AsciiEncoder, image: &RgbaImage) -> String {
+        let (columns, rows) = (image.width() / 2, image.height() / 4);
+        (0..rows).map(|y| (0..columns).map(|x| encoder.encode_cell(image, x, y, 127).ch).collect::<String>() + "\n").collect()
+    }
+
+    #[test]
+    fn ramp_by_luminance() {
+        let levels = [0, 63, 64, 130, 200, 255];
+        let image = RgbaImage::from_fn(12, 4, |x, _| {
+            let level = levels[x as usize / 2];
+            Rgba([level, level, level, 255])
+        });
+        let encoder = AsciiEncoder::new(&image, 127, &RAMP, false);
+        assert_eq!(glyphs(&encoder, &image), "  .o@@\n");
+
+        let cell = encoder.encode_cell(&image, 3, 0, 127);
+        assert_eq!(cell, Cell { ch: 'o', fg: Some(Rgba([130, 130, 130, 255])), bg: None });
+    }
+
+    #[test]
+    fn transparent_pixels_count_as_black() {
+        // Half of the pixels are transparent, which halves the luminance.
+        let image = RgbaImage::from_fn(2, 4, |_, y| if y < 2 { Rgba([255, 255, 255, 255]) } else { Rgba([0, 0, 0, 0]) });
+        let encoder = AsciiEncoder::new(&image, 127, &RAMP, false);
+        assert_eq!(encoder.encode_cell(&image, 0, 0, 127), Cell { ch: '.', fg: Some(Rgba([255, 255, 255, 255])), bg: None });
+    }
+
+    #[test]
+    fn edge_glyphs() {
+        let white = Rgba([255, 255, 255, 255]);
+        let black = Rgba([0, 0, 0, 255]);
+        let edges = |inside: fn(u32, u32) -> bool| {
+            let image = RgbaImage::from_fn(16, 16, |x, y| if inside(x, y) { white } else { black });
+            let encoder = AsciiEncoder::new(&image, 127, &RAMP, true);
+            glyphs(&encoder, &image)
+        };
+
+        assert_eq!(edges(|x, _| x >= 8), concat!(
+            "   ||@@@\n",
+            "   ||@@@\n",
+            "   ||@@@\n",
+            "   ||@@@\n",
+        ));
+        assert_eq!(edges(|_, y| y >= 8), concat!(
+            "        \n",
+            "________\n",
+            "--------\n",
+            "@@@@@@@@\n",
+        ));
+        assert_eq!(edges(|x, y| x + y >= 16), concat!(
+            "     ///\n",
+            "   /////\n",
+            " /////@@\n",
+            "||//@@@@\n",
+        ));
+        assert_eq!(edges(|x, y| x >= y), concat!(
+            r"\\\@@@@@", "\n",
+            r"\\\\\@@@", "\n",
+            r"  \\\\\@", "\n",
+            r"    \\||", "\n",
+        ));
+    }
+}
@@ -0,0 +1,277 @@
+use image::{Rgba, RgbaImage};
+
+use crate::cells::{Cell, CellEncoder};
+
+/// Encodes cells of `columns` x `rows` pixels using block element glyphs.
+///
+/// For each cell the pixels are split into a foreground and a background
+/// group, picking the split with the smallest color error, and the glyph
+/// that draws exactly the foreground group is used.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockEncoder {
+    columns: u32,
+    rows: u32,
+    /// Glyph for a bit mask of lit pixels. Bit `row * columns + column` is
+    /// the pixel at that position.
+    glyph: fn(u32) -> char,
+}
+
+impl BlockEncoder {
+    /// `▀`, `▄` and `█`, 1x2 pixels per cell.
+    pub const HALF: BlockEncoder = BlockEncoder { columns: 1, rows: 2, glyph: half_block_glyph };
+
+    /// Quadrant block elements, 2x2 pixels per cell.
+    pub const QUADRANT: BlockEncoder = BlockEncoder { columns: 2, rows: 2, glyph: quadrant_glyph };
+
+    /// Sextants from Symbols for Legacy Computing, 2x3 pixels per cell.
+    pub const SEXTANT: BlockEncoder = BlockEncoder { columns: 2, rows: 3, glyph: sextant_glyph };
+}
+
+fn half_block_glyph(mask: u32) -> char {
+    match mask {
+        0 => ' ',
+        1 => '▀',
+        2 => '▄',
+        _ => '█',
+    }
+}
+
+const QUADRANTS: [char; 16] = [
+    ' ', '▘', '▝', '▀',
+    '▖', '▌', '▞', '▛',
+    '▗', '▚', '▐', '▜',
+    '▄', '▙', '▟', '█',
+];
+
+#[inline]
+fn quadrant_glyph(mask: u32) -> char {
+    QUADRANTS[mask as usize & 0xF]
+}
+
+fn sextant_glyph(mask: u32) -> char {
+    // U+1FB00 to U+1FB3B enumerate all sextants in bit mask order, except
+    // for the ones that already exist as block elements.
+    match mask & 0x3F {
+        0  => ' ',
+        21 => '▌',
+        42 => '▐',
+        63 => '█',
+        mask => {
+            let skipped = (mask > 21) as u32 + (mask > 42) as u32;
+            char::from_u32(0x1FB00 + mask - 1 - skipped).unwrap_or('█')
+        }
+    }
+}
+
+/// Sum of the pixels of one group, for averaging and computing errors.
+#[derive(Debug, Default, Clone, Copy)]
+struct Sum {
+    count: u32,
+    rgb: [u32; 3],
+    squares: u32,
+}
+
+impl Sum {
+    #[inline]
+    fn add(&mut self, Rgba([r, g, b, _]): Rgba<u8>) {
+        self.count += 1;
+        self.rgb[0] += r as u32;
+        self.rgb[1] += g as u32;
+        self.rgb[2] += b as u32;
+        self.squares += r as u32 * r as u32 + g as u32 * g as u32 + b as u32 * b as u32;
+    }
+
+    /// Sum of squared distances of all pixels to their average.
+    #[inline]
+    fn error(&self) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+        let [r, g, b] = self.rgb;
+        let sum_squared = r as u64 * r as u64 + g as u64 * g as u64 + b as u64 * b as u64;
+        self.squares as u64 - sum_squared / self.count as u64
+    }
+
+    #[inline]
+    fn average(&self) -> Rgba<u8> {
+        let count = self.count.max(1);
+        let half = count / 2;
+        Rgba([
+            ((self.rgb[0] + half) / count) as u8,
+            ((self.rgb[1] + half) / count) as u8,
+            ((self.rgb[2] + half) / count) as u8,
+            255,
+        ])
+    }
+}
+
+impl CellEncoder for BlockEncoder {
+    #[inline]
+    fn cell_size(&self) -> (u32, u32) {
+        (self.columns, self.rows)
+    }
+
+    fn encode_cell(&self, image: &RgbaImage, x: u32, y: u32, alpha_threshold: u8) -> Cell {
+        let pixel_count = self.columns * self.rows;
+        let mut pixels = [Rgba([0, 0, 0, 0]); 8];
+        let mut opaque = 0u32;
+
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let px = x * self.columns + column;
+                let py = y * self.rows + row;
+                if px < image.width() && py < image.height() {
+                    let pixel = *image.get_pixel(px, py);
+                    let bit = row * self.columns + column;
+                    if pixel[3] >= alpha_threshold {
+                        opaque |= 1 << bit;
+                        pixels[bit as usize] = pixel;
+                    }
+                }
+            }
+        }
+
+        if opaque == 0 {
+            return Cell::EMPTY;
+        }
+
+        let full = (1 << pixel_count) - 1;
+        if opaque != full {
+            // Transparent pixels have to be the (default) background, so
+            // there is only one way to split the pixels.
+            let mut sum = Sum::default();
+            for bit in 0..pixel_count {
+                if opaque & (1 << bit) != 0 {
+                    sum.add(pixels[bit as usize]);
+                }
+            }
+            return Cell { ch: (self.glyph)(opaque), fg: Some(sum.average()), bg: None };
+        }
+
+        let mut best_mask = full;
+        let mut best_error = u64::MAX;
+        let mut best_fg = Sum::default();
+        let mut best_bg = Sum::default();
+
+        // Swapping foreground and background gives the same split, so it's
+        // enough to look at the masks that contain the first pixel. A solid
+        // block comes first so it wins over equally good splits.
+        let masks = std::iter::once(full).chain((1..full).filter(|mask| mask & 1 != 0));
+        for mask in masks {
+            let mut fg = Sum::default();
+            let mut bg = Sum::default();
+            for bit in 0..pixel_count {
+                if mask & (1 << bit) != 0 {
+                    fg.add(pixels[bit as usize]);
+                } else {
+                    bg.add(pixels[bit as usize]);
+                }
+            }
+            let error = fg.error() + bg.error();
+            if error < best_error {
+                best_error = error;
+                best_mask = mask;
+                best_fg = fg;
+                best_bg = bg;
+                if error == 0 {
+                    break;
+                }
+            }
+        }
+
+        if best_mask == full {
+            let color = best_fg.average();
+            return Cell { ch: '█', fg: Some(color), bg: None };
+        }
+
+        Cell {
+            ch: (self.glyph)(best_mask),
+            fg: Some(best_fg.average()),
+            bg: Some(best_bg.average()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    fn image(width: u32, pixels: &[Rgba<u8>]) -> RgbaImage {
+        let height = pixels.len() as u32 / width;
+        RgbaImage::from_fn(width, height, |x, y| pixels[(y * width + x) as usize])
+    }
+
+    #[test]
+    fn quadrant_glyphs() {
+        assert_eq!(quadrant_glyph(0b0001), '▘');
+        assert_eq!(quadrant_glyph(0b0011), '▀');
+        assert_eq!(quadrant_glyph(0b0101), '▌');
+        assert_eq!(quadrant_glyph(0b0110), '▞');
+        assert_eq!(quadrant_glyph(0b1001), '▚');
+        assert_eq!(quadrant_glyph(0b0111), '▛');
+        assert_eq!(quadrant_glyph(0b1110), '▟');
+    }
+
+    #[test]
+    fn sextant_glyphs() {
+        assert_eq!(sextant_glyph(0), ' ');
+        assert_eq!(sextant_glyph(1), '\u{1FB00}');
+        assert_eq!(sextant_glyph(20), '\u{1FB13}');
+        assert_eq!(sextant_glyph(21), '▌');
+        assert_eq!(sextant_glyph(22), '\u{1FB14}');
+        assert_eq!(sextant_glyph(42), '▐');
+        assert_eq!(sextant_glyph(43), '\u{1FB28}');
+        assert_eq!(sextant_glyph(62), '\u{1FB3B}');
+        assert_eq!(sextant_glyph(63), '█');
+
+        let mut glyphs: Vec<char> = (0..64).map(sextant_glyph).collect();
+        glyphs.sort_unstable();
+        glyphs.dedup();
+        assert_eq!(glyphs.len(), 64);
+    }
+
+    #[test]
+    fn two_color_split() {
+        let quadrant = image(2, &[RED, BLUE, BLUE, RED]);
+        assert_eq!(BlockEncoder::QUADRANT.encode_cell(&quadrant, 0, 0, 127), Cell { ch: '▚', fg: Some(RED), bg: Some(BLUE) });
+
+        let sextant = image(2, &[BLUE, RED, BLUE, RED, BLUE, RED]);
+        assert_eq!(BlockEncoder::SEXTANT.encode_cell(&sextant, 0, 0, 127), Cell { ch: '▌', fg: Some(BLUE), bg: Some(RED) });
+
+        let sextant = image(2, &[RED, RED, RED, BLUE, BLUE, BLUE]);
+        assert_eq!(BlockEncoder::SEXTANT.encode_cell(&sextant, 0, 0, 127), Cell { ch: '\u{1FB06}', fg: Some(RED), bg: Some(BLUE) });
+    }
+
+    #[test]
+    fn split_with_smallest_error() {
+        let dark_red = Rgba([205, 0, 0, 255]);
+        let quadrant = image(2, &[RED, dark_red, BLUE, BLUE]);
+        assert_eq!(BlockEncoder::QUADRANT.encode_cell(&quadrant, 0, 0, 127), Cell {
+            ch: '▀',
+            fg: Some(Rgba([230, 0, 0, 255])),
+            bg: Some(BLUE),
+        });
+
+        let solid = image(2, &[RED, RED, RED, RED]);
+        assert_eq!(BlockEncoder::QUADRANT.encode_cell(&solid, 0, 0, 127), Cell { ch: '█', fg: Some(RED), bg: None });
+    }
+
+    #[test]
+    fn transparent_pixels_are_background() {
+        let quadrant = image(2, &[CLEAR, RED, BLUE, CLEAR]);
+        assert_eq!(BlockEncoder::QUADRANT.encode_cell(&quadrant, 0, 0, 127), Cell {
+            ch: '▞',
+            fg: Some(Rgba([128, 0, 128, 255])),
+            bg: None,
+        });
+
+        // Pixels outside of the image count as transparent.
+        let half = image(2, &[RED, RED]);
+        assert_eq!(BlockEncoder::QUADRANT.encode_cell(&half, 0, 0, 127), Cell { ch: '▀', fg: Some(RED), bg: None });
+        assert_eq!(BlockEncoder::QUADRANT.encode_cell(&image(2, &[CLEAR; 4]), 0, 0, 127), Cell::EMPTY);
+    }
+}
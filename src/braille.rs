@@ -0,0 +1,182 @@
+use image::{Rgba, RgbaImage};
+
+use crate::cells::{Cell, CellEncoder};
+use crate::dither::{dither_mask, Dither};
+
+/// Bit of the braille pattern for the dot at (`x`, `y`) of a cell.
+const DOTS: [[u32; 2]; 4] = [
+    [0x01, 0x08],
+    [0x02, 0x10],
+    [0x04, 0x20],
+    [0x40, 0x80],
+];
+
+/// Encodes 2x4 pixels per cell as braille patterns (U+2800 to U+28FF).
+///
+/// Which dots are lit is decided for the whole image up front. Error
+/// diffusion stays within each cell, so that redrawing only the changed
+/// cells of an animation gives the same result as redrawing all of them.
+/// Each cell gets the average color of its lit pixels as foreground color.
+#[derive(Debug, Clone)]
+pub struct BrailleEncoder {
+    width: usize,
+    lit: Vec<bool>,
+}
+
+impl BrailleEncoder {
+    pub fn new(image: &RgbaImage, alpha_threshold: u8, threshold: u8, dither: Dither) -> Self {
+        Self {
+            width: image.width() as usize,
+            lit: dither_mask(image, alpha_threshold, threshold, dither, (2, 4)),
+        }
+    }
+}
+
+impl CellEncoder for BrailleEncoder {
+    #[inline]
+    fn cell_size(&self) -> (u32, u32) {
+        (2, 4)
+    }
+
+    fn encode_cell(&self, image: &RgbaImage, x: u32, y: u32, _alpha_threshold: u8) -> Cell {
+        let mut pattern = 0;
+        let mut count = 0;
+        let mut sum = [0u32; 3];
+
+        for (dy, row) in DOTS.iter().enumerate() {
+            for (dx, &bit) in row.iter().enumerate() {
+                let px = x * 2 + dx as u32;
+                let py = y * 4 + dy as u32;
+                if px >= image.width() || py >= image.height() {
+                    continue;
+                }
+                if self.lit[py as usize * self.width + px as usize] {
+                    let Rgba([r, g, b, _]) = *image.get_pixel(px, py);
+                    pattern |= bit;
+                    count += 1;
+                    sum[0] += r as u32;
+                    sum[1] += g as u32;
+                    sum[2] += b as u32;
+                }
+            }
+        }
+
+        if pattern == 0 {
+            return Cell::EMPTY;
+        }
+
+        let half = count / 2;
+        let color = Rgba([
+            ((sum[0] + half) / count) as u8,
+            ((sum[1] + half) / count) as u8,
+            ((sum[2] + half) / count) as u8,
+            255,
+        ]);
+
+        Cell {
+            ch: char::from_u32(0x2800 + pattern).unwrap_or(' '),
+            fg: Some(color),
+            bg: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cells::cells_to_ansi_into;
+    use crate::color_depth::ColorDepth;
+
+    /// A cell of the screen: glyph and SGR codes of its colors.
+    type ScreenCell = (char, String, String);
+
+    /// Applies the output of `cells_to_ansi_into` to `screen`, starting at
+    /// its top-left corner.
+    fn apply(output: &str, screen: &mut [Vec<ScreenCell>]) {
+        let mut x = 0;
+        let mut y = 0;
+        let mut fg = String::new();
+        let mut bg = String::new();
+        let mut chars = output.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\x1B' {
+                // A space doesn't show its foreground color.
+                let shown_fg = if ch == ' ' { String::new() } else { fg.clone() };
+                screen[y][x] = (ch, shown_fg, bg.clone());
+                x += 1;
+                continue;
+            }
+
+            assert_eq!(chars.next(), Some('['));
+            let mut params = String::new();
+            let command = loop {
+                let ch = chars.next().unwrap();
+                if ch.is_ascii_alphabetic() {
+                    break ch;
+                }
+                params.push(ch);
+            };
+            let count = params.parse().unwrap_or(1);
+            match command {
+                'A' => y -= count,
+                'B' => y += count,
+                'C' => x += count,
+                'D' => x -= count,
+                'm' if params == "0" => {
+                    fg.clear();
+                    bg.clear();
+                }
+                'm' if params.starts_with("38;") => fg = params,
+                'm' if params.starts_with("48;") => bg = params,
+                _ => panic!("unexpected sequence: {params}{command}"),
+            }
+        }
+    }
+
+    fn encode(prev_frame: &RgbaImage, image: &RgbaImage, dither: Dither, screen: &mut [Vec<ScreenCell>]) -> usize {
+        let braille = BrailleEncoder::new(image, 127, 128, dither);
+        let mut lines = String::new();
+        cells_to_ansi_into(&braille, prev_frame, image, 127, false, ColorDepth::TrueColor, &mut lines);
+        apply(&lines, screen);
+        lines.len()
+    }
+
+    #[test]
+    fn changed_cells_match_full_redraw() {
+        let first = RgbaImage::from_fn(32, 32, |x, y| Rgba([(x * 8) as u8, (y * 8) as u8, ((x + y) * 4) as u8, 255]));
+        let mut second = first.clone();
+        for y in 5..11 {
+            for x in 3..9 {
+                second.put_pixel(x, y, Rgba([255, 255, 255, 255]));
+            }
+        }
+        let blank = RgbaImage::new(32, 32);
+
+        for dither in [Dither::None, Dither::Bayer4, Dither::BlueNoise, Dither::FloydSteinberg, Dither::Atkinson, Dither::Sierra] {
+            let mut diffed = vec![vec![(' ', String::new(), String::new()); 16]; 8];
+            encode(&blank, &first, dither, &mut diffed);
+            let diff_len = encode(&first, &second, dither, &mut diffed);
+
+            let mut full = vec![vec![(' ', String::new(), String::new()); 16]; 8];
+            let full_len = encode(&blank, &second, dither, &mut full);
+
+            assert_eq!(diffed, full, "{dither}");
+            assert!(diff_len < full_len, "{dither}");
+        }
+    }
+
+    #[test]
+    fn dots_and_color() {
+        let mut image = RgbaImage::new(2, 4);
+        image.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        image.put_pixel(1, 3, Rgba([255, 255, 0, 255]));
+        image.put_pixel(1, 1, Rgba([40, 40, 40, 255]));
+
+        let braille = BrailleEncoder::new(&image, 127, 128, Dither::None);
+        assert_eq!(braille.encode_cell(&image, 0, 0, 127), Cell { ch: '⢁', fg: Some(Rgba([255, 255, 128, 255])), bg: None });
+
+        let empty = RgbaImage::new(2, 4);
+        let braille = BrailleEncoder::new(&empty, 127, 128, Dither::None);
+        assert_eq!(braille.encode_cell(&empty, 0, 0, 127), Cell::EMPTY);
+    }
+}
@@ -66,7 +66,7 @@ impl FromStr for CanvasSize {
                 return Err(CanvasSizeParseError());
             };
 
-            if let Some(_) = items.next() {
+            if items.next().is_some() {
                 return Err(CanvasSizeParseError());
             }
 
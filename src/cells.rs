@@ -0,0 +1,287 @@
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::color_depth::ColorDepth;
+use crate::image_to_ansi::{move_cursor, write_bg, write_fg};
+use crate::palette::Palette;
+
+/// One character cell of the terminal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cell {
+    pub ch: char,
+    /// `None` means the terminal's default foreground color.
+    pub fg: Option<Rgba<u8>>,
+    /// `None` means the terminal's default background color, which is what
+    /// transparent pixels are rendered as.
+    pub bg: Option<Rgba<u8>>,
+}
+
+impl Cell {
+    pub const EMPTY: Cell = Cell { ch: ' ', fg: None, bg: None };
+
+    /// A space doesn't show its foreground color.
+    #[inline]
+    pub fn needs_fg(&self) -> bool {
+        self.ch != ' '
+    }
+
+    /// A full block doesn't show its background color.
+    #[inline]
+    pub fn needs_bg(&self) -> bool {
+        self.ch != '█'
+    }
+
+    /// The cell with its colors mapped to the palette of `color_depth`, the
+    /// way the terminal shows them. Encoders average several pixels per
+    /// cell, which gives colors between the palette entries even for a
+    /// dithered image. If both colors end up the same the cell becomes a
+    /// full block.
+    pub fn quantized(self, color_depth: ColorDepth) -> Cell {
+        // Monochrome doesn't write any colors.
+        if color_depth.is_monochrome() {
+            return self;
+        }
+        let Some(palette) = Palette::for_color_depth(color_depth) else {
+            return self;
+        };
+
+        let quantize = |color: Option<Rgba<u8>>| color.map(|Rgba([r, g, b, a])| {
+            let Rgb([r, g, b]) = palette.quantize(Rgb([r, g, b]));
+            Rgba([r, g, b, a])
+        });
+        let fg = quantize(self.fg);
+        let bg = quantize(self.bg);
+
+        if fg.is_some() && fg == bg && self.needs_fg() && self.needs_bg() {
+            return Cell { ch: '█', fg, bg: None };
+        }
+        Cell { ch: self.ch, fg, bg }
+    }
+}
+
+/// Maps blocks of pixels to character cells.
+pub trait CellEncoder {
+    /// Width and height in pixels of the image area covered by one cell.
+    fn cell_size(&self) -> (u32, u32);
+
+    /// Number of neighbouring cells in each direction whose pixels influence
+    /// the glyph of a cell, so it has to be redrawn when they change.
+    #[inline]
+    fn context(&self) -> u32 {
+        0
+    }
+
+    /// Encodes the cell at column `x` and row `y`. Pixels of the block that
+    /// lie outside of `image` count as transparent.
+    fn encode_cell(&self, image: &RgbaImage, x: u32, y: u32, alpha_threshold: u8) -> Cell;
+}
+
+/// Whether any pixel of the block of cell (`x`, `y`) differs between
+/// `prev_frame` and `image`.
+#[inline]
+pub fn cell_changed(prev_frame: &RgbaImage, image: &RgbaImage, x: u32, y: u32, cell_width: u32, cell_height: u32) -> bool {
+    let x1 = x * cell_width;
+    let y1 = y * cell_height;
+    let x2 = (x1 + cell_width).min(image.width());
+    let y2 = (y1 + cell_height).min(image.height());
+
+    for py in y1..y2 {
+        for px in x1..x2 {
+            if image.get_pixel(px, py) != prev_frame.get_pixel(px, py) {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+/// Tracks the currently set SGR colors, so that escape sequences are only
+/// written when a color actually changes.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct SgrState {
+    fg: Option<Rgba<u8>>,
+    bg: Option<Rgba<u8>>,
+}
+
+impl SgrState {
+    pub(crate) fn set_colors(&mut self, cell: &Cell, color_depth: ColorDepth, lines: &mut String) {
+        // Monochrome only uses the terminal's default colors.
+        if color_depth.is_monochrome() {
+            return;
+        }
+
+        let fg = if cell.needs_fg() { Some(cell.fg) } else { None };
+        let bg = if cell.needs_bg() { Some(cell.bg) } else { None };
+
+        if (fg == Some(None) && self.fg.is_some()) || (bg == Some(None) && self.bg.is_some()) {
+            self.reset(lines);
+        }
+
+        if let Some(Some(color)) = bg {
+            if self.bg != Some(color) {
+                write_bg(color_depth, color, lines);
+                self.bg = Some(color);
+            }
+        }
+
+        if let Some(Some(color)) = fg {
+            if self.fg != Some(color) {
+                write_fg(color_depth, color, lines);
+                self.fg = Some(color);
+            }
+        }
+    }
+
+    /// Whether colors are set that a reset would clear.
+    #[inline]
+    pub(crate) fn is_set(&self) -> bool {
+        self.fg.is_some() || self.bg.is_some()
+    }
+
+    #[inline]
+    pub(crate) fn reset(&mut self, lines: &mut String) {
+        lines.push_str("\x1B[0m");
+        self.fg = None;
+        self.bg = None;
+    }
+}
+
+/// Renders `image` with the given cell encoder, only emitting the cells
+/// that differ from `prev_frame`. Works like
+/// [`image_to_ansi_into`](crate::image_to_ansi::image_to_ansi_into) for
+/// encoders other than half blocks.
+pub fn cells_to_ansi_into<E: CellEncoder + ?Sized>(encoder: &E, prev_frame: &RgbaImage, image: &RgbaImage, alpha_threshold: u8, full_width: bool, color_depth: ColorDepth, lines: &mut String) {
+    let (cell_width, cell_height) = encoder.cell_size();
+    let context = encoder.context();
+    let columns = image.width().div_ceil(cell_width);
+    let rows = image.height().div_ceil(cell_height);
+
+    lines.clear();
+
+    if rows == 0 || columns == 0 {
+        return;
+    }
+
+    let mut curr_x = 0;
+    let mut curr_y = 0;
+
+    for y in 0..rows {
+        let mut sgr = SgrState::default();
+
+        for x in 0..columns {
+            let changed = if context == 0 {
+                cell_changed(prev_frame, image, x, y, cell_width, cell_height)
+            } else {
+                let x1 = x.saturating_sub(context);
+                let y1 = y.saturating_sub(context);
+                (y1..=(y + context).min(rows - 1)).any(|ny|
+                    (x1..=(x + context).min(columns - 1)).any(|nx|
+                        cell_changed(prev_frame, image, nx, ny, cell_width, cell_height)))
+            };
+            if !changed {
+                continue;
+            }
+
+            let cell = encoder.encode_cell(image, x, y, alpha_threshold).quantized(color_depth);
+            move_cursor(curr_x, curr_y, x, y, lines);
+            sgr.set_colors(&cell, color_depth, lines);
+            lines.push(cell.ch);
+
+            // NOTE: Cursor location doesn't update at the end of the screen.
+            // This assumes that the image is rendered up to the end of the screen!
+            if full_width && (x + 1) == columns {
+                curr_x = x;
+            } else {
+                curr_x = x + 1;
+            }
+            curr_y = y;
+        }
+
+        if sgr.is_set() {
+            sgr.reset(lines);
+        }
+    }
+
+    // Just to ensure that the cursor is at the correct position after
+    // the image is rendered or when hitting Ctrl+C during sleep.
+    move_cursor(curr_x, curr_y, columns, rows - 1, lines);
+}
+
+/// Renders `image` with the given cell encoder as text that looks the same
+/// wherever it is printed, e.g. when saved to a file. Every row is written
+/// completely from left to right, ends with the colors reset and
+/// `line_end`. There is no cursor movement.
+pub fn cells_to_text_into<E: CellEncoder + ?Sized>(encoder: &E, image: &RgbaImage, alpha_threshold: u8, color_depth: ColorDepth, line_end: &str, lines: &mut String) {
+    let (cell_width, cell_height) = encoder.cell_size();
+    let columns = image.width().div_ceil(cell_width);
+    let rows = image.height().div_ceil(cell_height);
+
+    lines.clear();
+
+    for y in 0..rows {
+        let mut sgr = SgrState::default();
+        for x in 0..columns {
+            let cell = encoder.encode_cell(image, x, y, alpha_threshold).quantized(color_depth);
+            sgr.set_colors(&cell, color_depth, lines);
+            lines.push(cell.ch);
+        }
+        if sgr.is_set() {
+            sgr.reset(lines);
+        }
+        lines.push_str(line_end);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ascii::AsciiEncoder;
+    use crate::blocks::BlockEncoder;
+
+    #[test]
+    fn quantized_cells() {
+        let cell = Cell { ch: '▀', fg: Some(Rgba([250, 3, 2, 255])), bg: Some(Rgba([2, 1, 250, 255])) };
+        assert_eq!(cell.quantized(ColorDepth::TrueColor), cell);
+        assert_eq!(cell.quantized(ColorDepth::Monochrome), cell);
+        assert_eq!(cell.quantized(ColorDepth::Xterm256), Cell {
+            ch: '▀',
+            fg: Some(Rgba([255, 0, 0, 255])),
+            bg: Some(Rgba([0, 0, 255, 255])),
+        });
+
+        let cell = Cell { ch: '▚', fg: Some(Rgba([250, 3, 2, 255])), bg: Some(Rgba([240, 10, 0, 255])) };
+        assert_eq!(cell.quantized(ColorDepth::Ansi16), Cell { ch: '█', fg: Some(Rgba([255, 0, 0, 255])), bg: None });
+
+        assert_eq!(Cell::EMPTY.quantized(ColorDepth::Ansi8), Cell::EMPTY);
+    }
+
+    #[test]
+    fn averaged_colors_share_codes() {
+        // Every cell averages to a slightly different red.
+        let mut image = RgbaImage::new(8, 2);
+        for (x, _, pixel) in image.enumerate_pixels_mut() {
+            *pixel = Rgba([240 + x as u8, x as u8, 0, 255]);
+        }
+
+        let mut lines = String::new();
+        cells_to_text_into(&BlockEncoder::QUADRANT, &image, 127, ColorDepth::Xterm256, "\n", &mut lines);
+        assert_eq!(lines, "\x1B[38;5;196m████\x1B[0m\n");
+
+        cells_to_ansi_into(&BlockEncoder::QUADRANT, &RgbaImage::new(8, 2), &image, 127, false, ColorDepth::Xterm256, &mut lines);
+        assert_eq!(lines.matches("\x1B[38;5;196m").count(), 1);
+    }
+
+    #[test]
+    fn monochrome_text_is_plain() {
+        let image = RgbaImage::from_fn(8, 8, |x, _| Rgba([x as u8 * 36, 0, 200, 255]));
+        let ramp: Vec<char> = " .:-=+*#%@".chars().collect();
+        let ascii = AsciiEncoder::new(&image, 127, &ramp, false);
+
+        let mut lines = String::new();
+        cells_to_text_into(&ascii, &image, 127, ColorDepth::Monochrome, "\n", &mut lines);
+        assert_eq!(lines, " ..:\n ..:\n");
+
+        cells_to_ansi_into(&ascii, &RgbaImage::new(8, 8), &image, 127, false, ColorDepth::Monochrome, &mut lines);
+        assert!(!lines.contains('m'), "{lines:?}");
+    }
+}
@@ -1,8 +1,5 @@
 pub mod args;
-pub mod canvas_size;
-pub mod color;
+pub mod cell_aspect;
 pub mod filter;
+pub mod format;
 pub mod line_end;
-pub mod size;
-pub mod style;
-
@@ -1,18 +1,50 @@
 use std::ffi::OsString;
+use std::path::PathBuf;
 
 use clap::Parser;
 use image::imageops;
 
-use super::{canvas_size::CanvasSize, color::Color, filter::Filter, line_end::LineEnd, style::Style};
+use ansi_img::canvas_size::CanvasSize;
+use ansi_img::color::Color;
+use ansi_img::color_depth::ColorDepth;
+use ansi_img::dither::Dither;
+use ansi_img::encoder::Encoder;
+use ansi_img::ramp::Ramp;
+use ansi_img::style::Style;
+
+use super::{cell_aspect::CellAspect, filter::Filter, format::Format, line_end::LineEnd};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Args {
     /// Times to loop the animation.
     /// 
-    /// Negative values mean infinite looping.
-    #[arg(short, long, default_value_t = -1)]
-    pub loop_count: i64,
+    /// Negative values mean infinite looping. Defaults to the loop count
+    /// stored in the GIF, APNG or WebP file.
+    #[arg(short, long, allow_negative_numbers = true)]
+    pub loop_count: Option<i64>,
+
+    /// Factor to speed up animations by.
+    #[arg(short = 'x', long, default_value_t = 1.0)]
+    pub speed: f64,
+
+    /// Play animations at this many frames per second, ignoring the delays
+    /// stored in the file.
+    #[arg(short = 'F', long)]
+    pub fps: Option<f64>,
+
+    /// Delay in milliseconds to use for frames with a delay of 10 ms or
+    /// less.
+    /// 
+    /// Browsers use 100 here, because such delays usually weren't meant to
+    /// be taken literally.
+    #[arg(short, long)]
+    pub min_delay: Option<u64>,
+
+    /// Most frames per second to show. Frames are dropped when showing them
+    /// would exceed this or when painting can't keep up with the animation.
+    #[arg(short = 'M', long)]
+    pub max_fps: Option<f64>,
 
     /// Placement and scaling.
     /// 
@@ -28,6 +60,8 @@ pub struct Args {
     /// x and y can be * to center within the canvas.
     /// 
     /// z is a zoom value. It is either a whole number >= 1 or a fraction <= 1/2.
+    /// It scales canvas pixels as they are, like w and h given together, so
+    /// these sizes aren't corrected for the cell aspect ratio.
     /// 
     /// w and h can be * so it's derived from the respective other value.
     #[arg(short, long, default_value_t = Style::ShrinkToFit)]
@@ -42,6 +76,19 @@ pub struct Args {
     #[arg(short, long, default_value_t = CanvasSize::Window)]
     pub canvas_size: CanvasSize,
 
+    /// Width divided by height of a character cell, so that images keep
+    /// their aspect ratio.
+    /// 
+    /// `auto` uses the cell size in pixels reported by the terminal and
+    /// falls back to 1:2.
+    /// 
+    /// Values:{n}
+    /// - auto{n}
+    /// - <width>:<height>{n}
+    /// - <ratio>
+    #[arg(short = 'A', long, default_value_t = CellAspect::Auto)]
+    pub cell_aspect: CellAspect,
+
     #[arg(short, long, default_value_t = 127)]
     pub alpha_threshold: u8,
 
@@ -64,15 +111,123 @@ pub struct Args {
     #[arg(short, long, default_value_t = Color::Transparent)]
     pub background_color: Color,
 
+    /// Colors the terminal can display.
+    /// 
+    /// Pixels are mapped to the perceptually nearest color of the chosen
+    /// palette. `auto` looks at COLORTERM, TERM and terminfo and, if that
+    /// isn't conclusive, asks the terminal.
+    /// 
+    /// Values:{n}
+    /// - auto{n}
+    /// - truecolor (or 24bit){n}
+    /// - 256 (or xterm-256){n}
+    /// - 16 (or ansi-16){n}
+    /// - 8 (or ansi-8){n}
+    /// - monochrome (or mono)
+    #[arg(short = 'C', long, default_value_t = ColorDepth::TrueColor)]
+    pub color_depth: ColorDepth,
+
+    /// Dithering used when the color depth is limited and for choosing the
+    /// dots of braille characters.
+    /// 
+    /// Bayer and blue noise are ordered dithering methods and stay stable
+    /// between frames. The error diffusion methods (floyd-steinberg,
+    /// atkinson, sierra) look better on still images, but flicker in
+    /// animations. `auto` uses floyd-steinberg for still images and
+    /// blue-noise for animations.
+    /// 
+    /// Values:{n}
+    /// - auto{n}
+    /// - none{n}
+    /// - bayer2{n}
+    /// - bayer4{n}
+    /// - bayer8{n}
+    /// - floyd-steinberg{n}
+    /// - atkinson{n}
+    /// - sierra{n}
+    /// - blue-noise
+    #[arg(short, long, default_value_t = Dither::Auto)]
+    pub dither: Dither,
+
+    /// Characters used to draw the pixels.
+    /// 
+    /// Values:{n}
+    /// - half-block: ▀ ▄ █, 1x2 pixels per cell{n}
+    /// - quadrant: ▘ ▚ ▙ etc., 2x2 pixels per cell{n}
+    /// - sextant: Symbols for Legacy Computing, 2x3 pixels per cell{n}
+    /// - braille: ⠿ etc., 2x4 dots per cell{n}
+    /// - ascii: characters of `--ramp`, 2x4 pixels per cell{n}
+    /// - ascii-edges: like ascii, but draws edges as / \ | _ -{n}
+    /// - sixel: DEC sixel graphics, one pixel per screen pixel{n}
+    /// - kitty: kitty graphics protocol, true color with alpha{n}
+    /// - iterm2: iTerm2 inline images (OSC 1337), also WezTerm
+    /// 
+    /// Quadrants and sextants can only show two colors per cell, so they are
+    /// best for logos and line art. Braille only has one color per cell and
+    /// lights the dots of bright pixels (see `--threshold` and `--dither`).
+    /// 
+    /// The ASCII encoders pick characters by brightness and color them.
+    /// With `--color-depth monochrome` no colors are written, so together
+    /// with `--output` they give plain text.
+    /// 
+    /// Sixel ignores `--color-depth` and picks up to 256 colors that fit
+    /// the image best. Kitty uploads animations once and lets the terminal
+    /// play them. iTerm2 gets the original file if it doesn't have to be
+    /// moved or put on a background, and PNGs of the painted canvas
+    /// otherwise.
+    #[arg(short, long, default_value_t = Encoder::HalfBlock)]
+    pub encoder: Encoder,
+
+    /// Luminance (0 to 255) at which a braille dot is lit.
+    #[arg(short, long, default_value_t = 128)]
+    pub threshold: u8,
+
+    /// Characters used by the ASCII encoders, from dark to bright.
+    #[arg(short, long, default_value_t = Ramp::default())]
+    pub ramp: Ramp,
+
+    /// Compress image data sent with the kitty graphics protocol.
+    #[arg(short = 'z', long, default_value_t = false)]
+    pub compress: bool,
+
     /// Line ending to use.
     /// 
     /// Values:{n}
     /// - Cr{n}
     /// - Lf{n}
     /// - CrLf
-    #[arg(short, long, default_value_t = LineEnd::Lf)]
+    #[arg(short = 'L', long, default_value_t = LineEnd::Lf)]
     pub line_end: LineEnd,
 
+    /// Write the images as text to this file instead of showing them, or
+    /// to stdout for `-`.
+    /// 
+    /// The text has no cursor movement, so it looks the same wherever it is
+    /// printed. Every row ends with a reset, if colors were set, and the
+    /// line ending. Animations are written as their first frame, and
+    /// graphics encoders are replaced by half blocks.
+    #[arg(short, long, conflicts_with = "grid")]
+    pub output: Option<PathBuf>,
+
+    /// Format of `--output`, which defaults to stdout if only this is set.
+    /// 
+    /// `html` writes a `<pre>` element per image for embedding in other
+    /// pages and `html-page` a standalone page. Cells of the same colors are
+    /// merged into one `<span>`.
+    /// 
+    /// Values:{n}
+    /// - ansi{n}
+    /// - html{n}
+    /// - html-page (or htmlpage)
+    #[arg(short = 'O', long, default_value_t = Format::Ansi, conflicts_with = "grid")]
+    pub format: Format,
+
+    /// Use CSS classes like `ansi-fg-196` instead of inline styles for HTML
+    /// with 256, 16 or 8 colors. The classes are defined in a `<style>`
+    /// element before the images.
+    #[arg(short = 'k', long, default_value_t = false)]
+    pub css_classes: bool,
+
     /// Don't clear screen and render image wherever the cursor currently is.
     #[arg(short, long, default_value_t = false)]
     pub inline: bool,
@@ -82,6 +237,62 @@ pub struct Args {
     #[arg(short, long, default_value_t = false)]
     pub no_padding: bool,
 
+    /// Keep still images on screen until a key is pressed and paint them
+    /// again when the terminal window is resized.
+    #[arg(short, long, visible_alias = "hold", default_value_t = false)]
+    pub watch: bool,
+
+    /// Show the images on the alternate screen, which leaves the contents
+    /// of the terminal untouched.
+    /// 
+    /// Defaults to on for animations, slideshows and `--watch` when not
+    /// using `--inline` and writing to a terminal. Use `--alt-screen=false`
+    /// to turn it off.
+    #[arg(short = 'E', long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub alt_screen: Option<bool>,
+
+    /// Seconds to show each still image when showing several files.
+    #[arg(short = 'D', long, default_value_t = 5.0)]
+    pub duration: f64,
+
+    /// Start over after the last file when showing several files.
+    #[arg(short = 'R', long, default_value_t = false)]
+    pub repeat: bool,
+
+    /// Show a status line with the frame, its delay and the speed below
+    /// animations.
+    /// 
+    /// Animations can be controlled with space (pause), left and right
+    /// (step back and forward), + and - (speed), r (restart) and q (quit).
+    #[arg(short = 'S', long, default_value_t = false)]
+    pub status: bool,
+
+    /// Show the files as thumbnails in a grid that fills the canvas.
+    /// 
+    /// If they don't all fit, the grid is split into pages that are shown
+    /// like several files.
+    #[arg(short, long, default_value_t = false)]
+    pub grid: bool,
+
+    /// Width of the thumbnails of `--grid` in columns.
+    #[arg(short = 'T', long, default_value_t = 24)]
+    pub thumbnail_width: u32,
+
+    /// Space between the thumbnails of `--grid` in columns and rows.
+    #[arg(short = 'G', long, default_value_t = 1)]
+    pub gutter: u32,
+
+    /// Show the file names under the thumbnails of `--grid`.
+    #[arg(short = 'N', long, default_value_t = false)]
+    pub captions: bool,
+
+    /// Image files to show. Several files are shown one after another, use
+    /// n, Enter or Page Down for the next, p, Backspace or Page Up for the
+    /// previous and q or Escape to quit. Arguments that aren't files are
+    /// expanded as glob patterns.
+    /// 
+    /// `-` reads the image from stdin, which is also the default if stdin
+    /// isn't a terminal. The format is guessed from its contents.
     #[arg()]
-    pub path: OsString,
+    pub paths: Vec<OsString>,
 }
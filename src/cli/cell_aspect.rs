@@ -0,0 +1,58 @@
+use std::{fmt::Display, str::FromStr};
+
+/// Width divided by height of a terminal character cell.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CellAspect {
+    /// Ask the kernel or the terminal for the cell size in pixels. Needs to
+    /// be resolved using [`detect_cell_aspect`](ansi_img::term::detect_cell_aspect)
+    /// before rendering.
+    #[default]
+    Auto,
+    Ratio(f64),
+}
+
+impl Display for CellAspect {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            CellAspect::Auto => "auto".fmt(f),
+            CellAspect::Ratio(ratio) => ratio.fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CellAspectParseError();
+
+impl Display for CellAspectParseError {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        "illegal cell aspect ratio".fmt(f)
+    }
+}
+
+impl std::error::Error for CellAspectParseError {}
+
+#[inline]
+fn parse_positive(value: &str) -> Result<f64, CellAspectParseError> {
+    match value.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() && value > 0.0 => Ok(value),
+        _ => Err(CellAspectParseError()),
+    }
+}
+
+impl FromStr for CellAspect {
+    type Err = CellAspectParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("auto") {
+            return Ok(CellAspect::Auto);
+        }
+
+        if let Some((width, height)) = value.split_once([':', '/']) {
+            Ok(CellAspect::Ratio(parse_positive(width)? / parse_positive(height)?))
+        } else {
+            Ok(CellAspect::Ratio(parse_positive(value)?))
+        }
+    }
+}
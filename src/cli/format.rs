@@ -0,0 +1,59 @@
+use std::{fmt::Display, str::FromStr};
+
+/// What `--output` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Format {
+    /// Text with ANSI escape sequences.
+    #[default]
+    Ansi,
+    /// A `<pre>` element per image.
+    Html,
+    /// A standalone HTML page with all images.
+    HtmlPage,
+}
+
+impl Format {
+    #[inline]
+    pub fn is_html(&self) -> bool {
+        matches!(self, Format::Html | Format::HtmlPage)
+    }
+}
+
+impl Display for Format {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Ansi => "ansi",
+            Format::Html => "html",
+            Format::HtmlPage => "html-page",
+        }.fmt(f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FormatParseError();
+
+impl Display for FormatParseError {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        "illegal format value".fmt(f)
+    }
+}
+
+impl std::error::Error for FormatParseError {}
+
+impl FromStr for Format {
+    type Err = FormatParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("ansi") {
+            Ok(Format::Ansi)
+        } else if value.eq_ignore_ascii_case("html") {
+            Ok(Format::Html)
+        } else if value.eq_ignore_ascii_case("html-page") || value.eq_ignore_ascii_case("htmlpage") {
+            Ok(Format::HtmlPage)
+        } else {
+            Err(FormatParseError())
+        }
+    }
+}
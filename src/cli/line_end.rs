@@ -25,13 +25,14 @@ impl Default for LineEnd {
     }
 }
 
-impl ToString for LineEnd {
-    fn to_string(&self) -> String {
+impl Display for LineEnd {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::Cr => "Cr",
             Self::Lf => "Lf",
             Self::CrLf => "CrLf",
-        }.to_string()
+        }.fmt(f)
     }
 }
 
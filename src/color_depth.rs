@@ -0,0 +1,85 @@
+use std::{fmt::Display, str::FromStr};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ColorDepth {
+    /// Detect what the terminal supports. Needs to be resolved using
+    /// [`detect_color_depth`](crate::term::detect_color_depth) before
+    /// rendering, otherwise it's treated as true color.
+    Auto,
+    /// 24-bit `38;2;r;g;b` colors.
+    #[default]
+    TrueColor,
+    /// The 6x6x6 color cube and gray ramp of the xterm 256 color palette.
+    Xterm256,
+    /// The 8 normal plus 8 bright ANSI colors.
+    Ansi16,
+    /// The 8 normal ANSI colors.
+    Ansi8,
+    /// No colors at all, only lit and unlit pixels.
+    Monochrome,
+}
+
+impl ColorDepth {
+    #[inline]
+    pub fn is_auto(&self) -> bool {
+        matches!(self, ColorDepth::Auto)
+    }
+
+    #[inline]
+    pub fn is_true_color(&self) -> bool {
+        matches!(self, ColorDepth::TrueColor)
+    }
+
+    #[inline]
+    pub fn is_monochrome(&self) -> bool {
+        matches!(self, ColorDepth::Monochrome)
+    }
+}
+
+impl Display for ColorDepth {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto       => "auto",
+            Self::TrueColor  => "truecolor",
+            Self::Xterm256   => "256",
+            Self::Ansi16     => "16",
+            Self::Ansi8      => "8",
+            Self::Monochrome => "monochrome",
+        }.fmt(f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ColorDepthParseError();
+
+impl Display for ColorDepthParseError {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        "illegal color depth".fmt(f)
+    }
+}
+
+impl std::error::Error for ColorDepthParseError {}
+
+impl FromStr for ColorDepth {
+    type Err = ColorDepthParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("auto") {
+            Ok(ColorDepth::Auto)
+        } else if value.eq_ignore_ascii_case("truecolor") || value.eq_ignore_ascii_case("24bit") || value.eq_ignore_ascii_case("24-bit") {
+            Ok(ColorDepth::TrueColor)
+        } else if value == "256" || value.eq_ignore_ascii_case("xterm-256") || value.eq_ignore_ascii_case("xterm256") {
+            Ok(ColorDepth::Xterm256)
+        } else if value == "16" || value.eq_ignore_ascii_case("ansi-16") || value.eq_ignore_ascii_case("ansi16") {
+            Ok(ColorDepth::Ansi16)
+        } else if value == "8" || value.eq_ignore_ascii_case("ansi-8") || value.eq_ignore_ascii_case("ansi8") {
+            Ok(ColorDepth::Ansi8)
+        } else if value.eq_ignore_ascii_case("monochrome") || value.eq_ignore_ascii_case("mono") {
+            Ok(ColorDepth::Monochrome)
+        } else {
+            Err(ColorDepthParseError())
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use image::{Pixel, Rgba, RgbaImage};
+
+/// What happens to the area of a frame before the next one is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposal {
+    /// Leave the frame on the canvas.
+    None,
+    /// Clear the area of the frame to transparent.
+    Background,
+    /// Restore the canvas to how it was before the frame was drawn.
+    Previous,
+}
+
+/// How the pixels of a frame are drawn onto the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Blend {
+    /// Replace the canvas pixels, including their alpha.
+    Source,
+    /// Alpha blend over the canvas pixels.
+    Over,
+}
+
+/// Keeps the canvas of an animation whose frames only cover part of it,
+/// honoring the disposal and blend method of each frame like browsers do.
+#[derive(Debug, Clone)]
+pub struct Compositor {
+    canvas: RgbaImage,
+    saved: Option<RgbaImage>,
+    /// Disposal and area (x, y, width, height) of the last frame.
+    last: Option<(Disposal, (u32, u32, u32, u32))>,
+}
+
+impl Compositor {
+    #[inline]
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            canvas: RgbaImage::new(width, height),
+            saved: None,
+            last: None,
+        }
+    }
+
+    #[inline]
+    pub fn canvas(&self) -> &RgbaImage {
+        &self.canvas
+    }
+
+    /// Disposes of the last frame and draws `frame` with its top left
+    /// corner at `left`, `top`. Returns the resulting canvas.
+    pub fn draw(&mut self, frame: &RgbaImage, left: u32, top: u32, disposal: Disposal, blend: Blend) -> &RgbaImage {
+        match self.last {
+            Some((Disposal::Background, (x, y, width, height))) => {
+                for y in y..y + height {
+                    for x in x..x + width {
+                        self.canvas.put_pixel(x, y, Rgba([0, 0, 0, 0]));
+                    }
+                }
+            }
+            Some((Disposal::Previous, _)) => {
+                if let Some(saved) = &self.saved {
+                    self.canvas.copy_from_slice(saved);
+                }
+            }
+            _ => {}
+        }
+
+        // There is nothing to restore for the first frame.
+        let disposal = if disposal == Disposal::Previous && self.last.is_none() {
+            Disposal::Background
+        } else {
+            disposal
+        };
+
+        if disposal == Disposal::Previous {
+            match &mut self.saved {
+                Some(saved) => saved.copy_from_slice(&self.canvas),
+                None => self.saved = Some(self.canvas.clone()),
+            }
+        }
+
+        let width  = frame.width().min(self.canvas.width().saturating_sub(left));
+        let height = frame.height().min(self.canvas.height().saturating_sub(top));
+
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = frame.get_pixel(x, y);
+                let target = self.canvas.get_pixel_mut(left + x, top + y);
+                match blend {
+                    Blend::Source => *target = *pixel,
+                    Blend::Over   => target.blend(pixel),
+                }
+            }
+        }
+
+        self.last = Some((disposal, (left.min(self.canvas.width()), top.min(self.canvas.height()), width, height)));
+        &self.canvas
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+    const RED:   Rgba<u8> = Rgba([255, 0, 0, 255]);
+    const BLUE:  Rgba<u8> = Rgba([0, 0, 255, 255]);
+    const HALF_BLUE: Rgba<u8> = Rgba([0, 0, 255, 128]);
+
+    fn solid(width: u32, height: u32, color: Rgba<u8>) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, color)
+    }
+
+    fn pixels(image: &RgbaImage) -> Vec<Rgba<u8>> {
+        image.pixels().copied().collect()
+    }
+
+    /// A compositor whose canvas is all red, drawn by a first frame that
+    /// stays on the canvas.
+    fn red_canvas() -> Compositor {
+        let mut compositor = Compositor::new(2, 2);
+        compositor.draw(&solid(2, 2, RED), 0, 0, Disposal::None, Blend::Source);
+        compositor
+    }
+
+    #[test]
+    fn source_replaces_pixels_including_alpha() {
+        let mut compositor = red_canvas();
+        let canvas = compositor.draw(&solid(1, 1, CLEAR), 1, 0, Disposal::None, Blend::Source);
+        assert_eq!(pixels(canvas), [RED, CLEAR, RED, RED]);
+    }
+
+    #[test]
+    fn over_blends_onto_pixels() {
+        let mut compositor = red_canvas();
+        let canvas = compositor.draw(&solid(1, 1, CLEAR), 1, 0, Disposal::None, Blend::Over);
+        assert_eq!(pixels(canvas), [RED, RED, RED, RED]);
+
+        let canvas = compositor.draw(&solid(1, 1, HALF_BLUE), 0, 1, Disposal::None, Blend::Over);
+        let blended = canvas.get_pixel(0, 1);
+        // `Rgba::blend` rounds the alpha of opaque results down a little.
+        assert!(blended[3] >= 254);
+        assert!(blended[0] > 0 && blended[0] < 255);
+        assert!(blended[2] > 0 && blended[2] < 255);
+    }
+
+    #[test]
+    fn disposal_none_keeps_frame() {
+        for blend in [Blend::Source, Blend::Over] {
+            let mut compositor = red_canvas();
+            compositor.draw(&solid(1, 1, BLUE), 0, 0, Disposal::None, blend);
+            let canvas = compositor.draw(&solid(1, 1, BLUE), 1, 1, Disposal::None, blend);
+            assert_eq!(pixels(canvas), [BLUE, RED, RED, BLUE]);
+        }
+    }
+
+    #[test]
+    fn disposal_background_clears_frame_area() {
+        for blend in [Blend::Source, Blend::Over] {
+            let mut compositor = red_canvas();
+            let canvas = compositor.draw(&solid(1, 2, BLUE), 1, 0, Disposal::Background, blend);
+            assert_eq!(pixels(canvas), [RED, BLUE, RED, BLUE]);
+
+            let canvas = compositor.draw(&solid(1, 1, BLUE), 0, 0, Disposal::None, blend);
+            assert_eq!(pixels(canvas), [BLUE, CLEAR, RED, CLEAR]);
+        }
+    }
+
+    #[test]
+    fn disposal_previous_restores_canvas() {
+        for blend in [Blend::Source, Blend::Over] {
+            let mut compositor = red_canvas();
+            let canvas = compositor.draw(&solid(2, 1, BLUE), 0, 0, Disposal::Previous, blend);
+            assert_eq!(pixels(canvas), [BLUE, BLUE, RED, RED]);
+
+            let canvas = compositor.draw(&solid(1, 1, BLUE), 1, 1, Disposal::None, blend);
+            assert_eq!(pixels(canvas), [RED, RED, RED, BLUE]);
+        }
+    }
+
+    #[test]
+    fn disposal_previous_restores_blended_area() {
+        let mut compositor = red_canvas();
+        compositor.draw(&solid(2, 2, HALF_BLUE), 0, 0, Disposal::Previous, Blend::Over);
+        let canvas = compositor.draw(&solid(1, 1, CLEAR), 0, 0, Disposal::None, Blend::Over);
+        assert_eq!(pixels(canvas), [RED, RED, RED, RED]);
+    }
+
+    #[test]
+    fn disposal_previous_on_first_frame_clears() {
+        for blend in [Blend::Source, Blend::Over] {
+            let mut compositor = Compositor::new(2, 2);
+            compositor.draw(&solid(2, 2, RED), 0, 0, Disposal::Previous, blend);
+            let canvas = compositor.draw(&solid(1, 1, BLUE), 0, 0, Disposal::None, blend);
+            assert_eq!(pixels(canvas), [BLUE, CLEAR, CLEAR, CLEAR]);
+        }
+    }
+
+    #[test]
+    fn frames_are_clipped_to_canvas() {
+        let mut compositor = red_canvas();
+        let canvas = compositor.draw(&solid(2, 2, BLUE), 1, 1, Disposal::Background, Blend::Source);
+        assert_eq!(pixels(canvas), [RED, RED, RED, BLUE]);
+
+        let canvas = compositor.draw(&solid(1, 1, BLUE), 5, 5, Disposal::None, Blend::Source);
+        assert_eq!(pixels(canvas), [RED, RED, RED, CLEAR]);
+    }
+}
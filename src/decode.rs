@@ -0,0 +1,450 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, Cursor, Read, Seek, SeekFrom};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::sync::Arc;
+
+use image::codecs::png::PngDecoder;
+use image::codecs::webp::WebPDecoder;
+use image::error::{DecodingError, ImageFormatHint, ImageResult};
+use image::io::Reader as ImageReader;
+use image::{AnimationDecoder, Delay, DynamicImage, Frame, Frames, ImageBuffer, ImageDecoder, ImageError, ImageFormat, Luma, LumaA, Rgb, RgbaImage};
+
+use crate::compositor::{Blend, Compositor, Disposal};
+
+#[inline]
+fn decoding_error(format: ImageFormat, error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(format), error))
+}
+
+/// Number of plays where 0 means forever, as in APNG and WebP files.
+#[inline]
+fn plays(count: u32) -> Option<u32> {
+    if count == 0 { None } else { Some(count) }
+}
+
+/// Reads the animation control chunk of a PNG file, which only APNG files
+/// have, and seeks back to where the file started.
+fn animation_control<R: BufRead + Seek>(reader: &mut R) -> ImageResult<Option<png::AnimationControl>> {
+    let start = reader.stream_position()?;
+    let decoder = png::Decoder::new(&mut *reader).read_info().map_err(|error| decoding_error(ImageFormat::Png, error))?;
+    let actl = decoder.info().animation_control().copied();
+    drop(decoder);
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(actl)
+}
+
+/// Reads the loop count of a WebP file and seeks back to where the file
+/// started.
+fn webp_loop_count<R: BufRead + Seek>(reader: &mut R) -> ImageResult<Option<u32>> {
+    let start = reader.stream_position()?;
+    let decoder = image_webp::WebPDecoder::new(&mut *reader).map_err(|error| decoding_error(ImageFormat::WebP, error))?;
+    let loop_count = match decoder.loop_count() {
+        image_webp::LoopCount::Forever => None,
+        image_webp::LoopCount::Times(count) => Some(u32::from(count.get())),
+    };
+    drop(decoder);
+    reader.seek(SeekFrom::Start(start))?;
+    Ok(loop_count)
+}
+
+/// Whether `frame` is the error that the WebP decoder of the image crate
+/// returns after the last frame instead of ending.
+fn is_past_last_webp_frame(frame: &ImageResult<Frame>) -> bool {
+    let Err(ImageError::Decoding(error)) = frame else {
+        return false;
+    };
+    let source = std::error::Error::source(error).and_then(|source| source.downcast_ref::<image_webp::DecodingError>());
+    matches!(source, Some(image_webp::DecodingError::NoMoreFrames))
+}
+
+/// Frames of a WebP file.
+fn webp_frames<'a, R: BufRead + Seek + 'a>(decoder: WebPDecoder<R>) -> Frames<'a> {
+    Frames::new(Box::new(decoder.into_frames().take_while(|frame| !is_past_last_webp_frame(frame))))
+}
+
+/// Frames of a GIF file, composed with their disposal methods.
+struct GifFrames<R: Read> {
+    decoder: gif::Decoder<R>,
+    compositor: Compositor,
+}
+
+impl<R: Read> GifFrames<R> {
+    fn new(reader: R) -> ImageResult<Self> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let decoder = options.read_info(reader).map_err(|error| decoding_error(ImageFormat::Gif, error))?;
+        let compositor = Compositor::new(decoder.width().into(), decoder.height().into());
+        Ok(Self { decoder, compositor })
+    }
+
+    #[inline]
+    fn dimensions(&self) -> (u32, u32) {
+        self.compositor.canvas().dimensions()
+    }
+
+    /// The NETSCAPE2.0 extension counts the repetitions after the first
+    /// play, and 0 means forever.
+    #[inline]
+    fn loop_count(&self) -> Option<u32> {
+        match self.decoder.repeat() {
+            gif::Repeat::Infinite => None,
+            gif::Repeat::Finite(repetitions) => Some(u32::from(repetitions) + 1),
+        }
+    }
+}
+
+impl<R: Read> Iterator for GifFrames<R> {
+    type Item = ImageResult<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = match self.decoder.read_next_frame() {
+            Ok(frame) => frame?,
+            Err(error) => return Some(Err(decoding_error(ImageFormat::Gif, error))),
+        };
+
+        let Some(image) = RgbaImage::from_raw(frame.width.into(), frame.height.into(), frame.buffer.to_vec()) else {
+            return Some(Err(decoding_error(ImageFormat::Gif, "frame data too short")));
+        };
+        let disposal = match frame.dispose {
+            gif::DisposalMethod::Any | gif::DisposalMethod::Keep => Disposal::None,
+            gif::DisposalMethod::Background => Disposal::Background,
+            gif::DisposalMethod::Previous => Disposal::Previous,
+        };
+        // The delay is in units of 10 ms.
+        let delay = Delay::from_numer_denom_ms(u32::from(frame.delay) * 10, 1);
+        let (left, top) = (frame.left.into(), frame.top.into());
+
+        // Transparent GIF pixels leave the canvas as it is.
+        let canvas = self.compositor.draw(&image, left, top, disposal, Blend::Over);
+        Some(Ok(Frame::from_parts(canvas.clone(), 0, 0, delay)))
+    }
+}
+
+/// Frames of an APNG file, composed with their dispose and blend
+/// operations.
+struct ApngFrames<R: BufRead + Seek> {
+    reader: png::Reader<R>,
+    compositor: Compositor,
+    remaining: u32,
+    buffer: Vec<u8>,
+}
+
+impl<R: BufRead + Seek> ApngFrames<R> {
+    fn new(reader: R) -> ImageResult<Self> {
+        let mut decoder = png::Decoder::new(reader);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(|error| decoding_error(ImageFormat::Png, error))?;
+
+        let info = reader.info();
+        let remaining = info.animation_control().map_or(0, |actl| actl.num_frames);
+        let compositor = Compositor::new(info.width, info.height);
+        let mut buffer = vec![0; reader.output_buffer_size()];
+
+        // The default image is not part of the animation if it has no
+        // frame control chunk.
+        if info.frame_control().is_none() {
+            reader.next_frame(&mut buffer).map_err(|error| decoding_error(ImageFormat::Png, error))?;
+        }
+
+        Ok(Self { reader, compositor, remaining, buffer })
+    }
+
+    fn next_frame(&mut self) -> ImageResult<Frame> {
+        let output = self.reader.next_frame(&mut self.buffer).map_err(|error| decoding_error(ImageFormat::Png, error))?;
+        let data = self.buffer[..output.buffer_size()].to_vec();
+        let (width, height) = (output.width, output.height);
+
+        let image = match output.color_type {
+            png::ColorType::Grayscale      => ImageBuffer::<Luma<u8>, _>::from_raw(width, height, data).map(|image| DynamicImage::ImageLuma8(image).into_rgba8()),
+            png::ColorType::GrayscaleAlpha => ImageBuffer::<LumaA<u8>, _>::from_raw(width, height, data).map(|image| DynamicImage::ImageLumaA8(image).into_rgba8()),
+            png::ColorType::Rgb            => ImageBuffer::<Rgb<u8>, _>::from_raw(width, height, data).map(|image| DynamicImage::ImageRgb8(image).into_rgba8()),
+            png::ColorType::Rgba           => RgbaImage::from_raw(width, height, data),
+            png::ColorType::Indexed        => None,
+        };
+        let Some(image) = image else {
+            return Err(decoding_error(ImageFormat::Png, "unexpected frame format"));
+        };
+
+        let Some(control) = self.reader.info().frame_control() else {
+            return Err(decoding_error(ImageFormat::Png, "frame without frame control chunk"));
+        };
+        let disposal = match control.dispose_op {
+            png::DisposeOp::None       => Disposal::None,
+            png::DisposeOp::Background => Disposal::Background,
+            png::DisposeOp::Previous   => Disposal::Previous,
+        };
+        let blend = match control.blend_op {
+            png::BlendOp::Source => Blend::Source,
+            png::BlendOp::Over   => Blend::Over,
+        };
+        // The delay is a fraction of seconds, where a denominator of 0 means
+        // hundredths.
+        let denominator = if control.delay_den == 0 { 100 } else { control.delay_den.into() };
+        let delay = Delay::from_numer_denom_ms(u32::from(control.delay_num) * 1000, denominator);
+        let (left, top) = (control.x_offset, control.y_offset);
+
+        let canvas = self.compositor.draw(&image, left, top, disposal, blend);
+        Ok(Frame::from_parts(canvas.clone(), 0, 0, delay))
+    }
+}
+
+impl<R: BufRead + Seek> Iterator for ApngFrames<R> {
+    type Item = ImageResult<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let frame = self.next_frame();
+        if frame.is_err() {
+            self.remaining = 0;
+        }
+        Some(frame)
+    }
+}
+
+/// A decoded image file.
+pub enum DecodedImage {
+    /// Width and height of the animation, its frames and how many times it
+    /// should be played according to the file, `None` meaning forever.
+    Animated(u32, u32, Vec<Frame>, Option<u32>),
+    Still(RgbaImage)
+}
+
+impl DecodedImage {
+    #[inline]
+    pub fn size(&self) -> (u32, u32) {
+        match self {
+            DecodedImage::Animated(width, height, ..) => (*width, *height),
+            DecodedImage::Still(img) => (img.width(), img.height()),
+        }
+    }
+
+    #[inline]
+    pub fn is_animated(&self) -> bool {
+        matches!(self, DecodedImage::Animated(..))
+    }
+}
+
+/// Decodes all frames of animated GIF, WebP and PNG files, and any other
+/// format supported by the image crate as a still image. The file is read
+/// into memory and decoded with [`decode_streaming`].
+pub fn decode<R: BufRead + Seek>(reader: ImageReader<R>) -> ImageResult<DecodedImage> {
+    let format = reader.format();
+    let mut data = Vec::new();
+    reader.into_inner().read_to_end(&mut data)?;
+    let data: Arc<[u8]> = data.into();
+
+    let open = move || {
+        let mut reader = ImageReader::new(Cursor::new(data.clone()));
+        if let Some(format) = format {
+            reader.set_format(format);
+        }
+        Ok(reader)
+    };
+
+    match decode_streaming(open)? {
+        StreamedImage::Animated(width, height, frames, loop_count) => {
+            let frames = frames.collect::<ImageResult<Vec<_>>>()?;
+            Ok(DecodedImage::Animated(width, height, frames, loop_count))
+        }
+        StreamedImage::Still(image) => Ok(DecodedImage::Still(image)),
+    }
+}
+
+/// Frames decoded ahead of playback by [`FrameStream`].
+pub const FRAME_QUEUE_LEN: usize = 8;
+
+/// A decoded image file whose animation frames are decoded on demand.
+pub enum StreamedImage {
+    /// Width and height of the animation, its frames and how many times it
+    /// should be played according to the file, `None` meaning forever.
+    Animated(u32, u32, FrameStream, Option<u32>),
+    Still(RgbaImage)
+}
+
+impl StreamedImage {
+    #[inline]
+    pub fn size(&self) -> (u32, u32) {
+        match self {
+            StreamedImage::Animated(width, height, ..) => (*width, *height),
+            StreamedImage::Still(img) => (img.width(), img.height()),
+        }
+    }
+
+    #[inline]
+    pub fn is_animated(&self) -> bool {
+        matches!(self, StreamedImage::Animated(..))
+    }
+}
+
+/// Animation frames that a thread decodes into a bounded queue, so that
+/// playback can start right away and only a few frames are held in memory.
+pub struct FrameStream {
+    receiver: Receiver<ImageResult<Frame>>,
+    ready: VecDeque<ImageResult<Frame>>,
+    spawn: Box<dyn Fn() -> Receiver<ImageResult<Frame>>>,
+}
+
+impl FrameStream {
+    /// Starts decoding from the first frame again. The file is opened and
+    /// decoded anew.
+    pub fn rewind(&mut self) {
+        self.receiver = (self.spawn)();
+        self.ready.clear();
+    }
+}
+
+impl Iterator for FrameStream {
+    type Item = ImageResult<Frame>;
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        self.ready.pop_front().or_else(|| self.receiver.recv().ok())
+    }
+}
+
+fn frames<'a, R: BufRead + Seek + 'a>(reader: ImageReader<R>) -> ImageResult<Frames<'a>> {
+    let frames = match reader.format() {
+        Some(ImageFormat::Gif) => Frames::new(Box::new(GifFrames::new(reader.into_inner())?)),
+        Some(ImageFormat::WebP) => webp_frames(WebPDecoder::new(reader.into_inner())?),
+        _ => Frames::new(Box::new(ApngFrames::new(reader.into_inner())?)),
+    };
+    Ok(frames)
+}
+
+/// Like [`decode`], but animation frames are decoded by a thread while
+/// they are played. `open` is called for every pass over the frames, so it
+/// has to return a reader at the start of the same file each time.
+pub fn decode_streaming<R, F>(open: F) -> ImageResult<StreamedImage>
+where
+    R: BufRead + Seek,
+    F: Fn() -> ImageResult<ImageReader<R>> + Send + Sync + 'static,
+{
+    let reader = open()?;
+    let (width, height, loop_count) = match reader.format() {
+        Some(ImageFormat::Gif) => {
+            let decoder = GifFrames::new(reader.into_inner())?;
+            let (width, height) = decoder.dimensions();
+            (width, height, decoder.loop_count())
+        },
+        Some(ImageFormat::WebP) => {
+            let mut reader = reader.into_inner();
+            let loop_count = webp_loop_count(&mut reader)?;
+            let decoder = WebPDecoder::new(reader)?;
+            if !decoder.has_animation() {
+                return Ok(StreamedImage::Still(DynamicImage::from_decoder(decoder)?.to_rgba8()));
+            }
+            let (width, height) = decoder.dimensions();
+            (width, height, loop_count)
+        },
+        Some(ImageFormat::Png) => {
+            let mut reader = reader.into_inner();
+            let Some(actl) = animation_control(&mut reader)? else {
+                return Ok(StreamedImage::Still(DynamicImage::from_decoder(PngDecoder::new(reader)?)?.to_rgba8()));
+            };
+            let (width, height) = PngDecoder::new(reader)?.dimensions();
+            (width, height, plays(actl.num_plays))
+        },
+        _ => return Ok(StreamedImage::Still(reader.decode()?.to_rgba8()))
+    };
+
+    let open = Arc::new(open);
+    let spawn = move || {
+        let (sender, receiver) = sync_channel(FRAME_QUEUE_LEN);
+        let open = open.clone();
+        std::thread::spawn(move || {
+            let frames = match open().and_then(frames) {
+                Ok(frames) => frames,
+                Err(error) => {
+                    let _ = sender.send(Err(error));
+                    return;
+                }
+            };
+            for frame in frames {
+                // Stops when the stream was dropped or rewound.
+                if sender.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+        receiver
+    };
+
+    let mut stream = FrameStream {
+        receiver: spawn(),
+        ready: VecDeque::new(),
+        spawn: Box::new(spawn),
+    };
+
+    // Files with a single frame are shown as still images.
+    let first = stream.next();
+    let second = stream.next();
+    match (first, second) {
+        (Some(first), None) => Ok(StreamedImage::Still(first?.into_buffer())),
+        (first, second) => {
+            stream.ready.extend(first);
+            stream.ready.extend(second);
+            Ok(StreamedImage::Animated(width, height, stream, loop_count))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gif(repeat: Option<gif::Repeat>) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut data, 2, 2, &[0, 0, 0, 255, 255, 255]).unwrap();
+            if let Some(repeat) = repeat {
+                encoder.set_repeat(repeat).unwrap();
+            }
+            for color in [0, 1] {
+                let frame = gif::Frame { width: 2, height: 2, delay: 10, buffer: vec![color; 4].into(), ..Default::default() };
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        data
+    }
+
+    fn apng(num_plays: u32) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut data, 2, 2);
+            encoder.set_color(png::ColorType::Grayscale);
+            encoder.set_animated(2, num_plays).unwrap();
+            let mut writer = encoder.write_header().unwrap();
+            writer.write_image_data(&[0; 4]).unwrap();
+            writer.write_image_data(&[255; 4]).unwrap();
+        }
+        data
+    }
+
+    fn streamed_loop_count(data: Vec<u8>) -> Option<u32> {
+        let data: Arc<[u8]> = data.into();
+        let open = move || Ok(ImageReader::new(Cursor::new(data.clone())).with_guessed_format()?);
+        match decode_streaming(open).unwrap() {
+            StreamedImage::Animated(2, 2, _, loop_count) => loop_count,
+            _ => panic!("not a 2x2 animation"),
+        }
+    }
+
+    #[test]
+    fn gif_repetitions_follow_the_first_play() {
+        assert_eq!(streamed_loop_count(gif(None)), Some(1));
+        assert_eq!(streamed_loop_count(gif(Some(gif::Repeat::Finite(0)))), Some(1));
+        assert_eq!(streamed_loop_count(gif(Some(gif::Repeat::Finite(2)))), Some(3));
+        assert_eq!(streamed_loop_count(gif(Some(gif::Repeat::Infinite))), None);
+    }
+
+    #[test]
+    fn apng_plays() {
+        assert_eq!(streamed_loop_count(apng(0)), None);
+        assert_eq!(streamed_loop_count(apng(1)), Some(1));
+        assert_eq!(streamed_loop_count(apng(3)), Some(3));
+    }
+}
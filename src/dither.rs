@@ -0,0 +1,620 @@
+use std::sync::OnceLock;
+use std::{fmt::Display, str::FromStr};
+
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::color_depth::ColorDepth;
+use crate::palette::{quantize_image, store_quantized, Palette};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Dither {
+    /// Floyd–Steinberg for still images and blue noise for animations.
+    #[default]
+    Auto,
+    None,
+    Bayer2,
+    Bayer4,
+    Bayer8,
+    FloydSteinberg,
+    Atkinson,
+    Sierra,
+    BlueNoise,
+}
+
+impl Dither {
+    /// Picks the actual dithering method for `Auto`. An unresolved `Auto`
+    /// doesn't dither at all.
+    #[inline]
+    pub fn resolve(self, animated: bool) -> Self {
+        match self {
+            Dither::Auto if animated => Dither::BlueNoise,
+            Dither::Auto => Dither::FloydSteinberg,
+            other => other,
+        }
+    }
+}
+
+impl Display for Dither {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto           => "auto",
+            Self::None           => "none",
+            Self::Bayer2         => "bayer2",
+            Self::Bayer4         => "bayer4",
+            Self::Bayer8         => "bayer8",
+            Self::FloydSteinberg => "floyd-steinberg",
+            Self::Atkinson       => "atkinson",
+            Self::Sierra         => "sierra",
+            Self::BlueNoise      => "blue-noise",
+        }.fmt(f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DitherParseError();
+
+impl Display for DitherParseError {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        "illegal dither value".fmt(f)
+    }
+}
+
+impl std::error::Error for DitherParseError {}
+
+impl FromStr for Dither {
+    type Err = DitherParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("auto") {
+            Ok(Dither::Auto)
+        } else if value.eq_ignore_ascii_case("none") {
+            Ok(Dither::None)
+        } else if value.eq_ignore_ascii_case("bayer2") || value.eq_ignore_ascii_case("bayer-2x2") {
+            Ok(Dither::Bayer2)
+        } else if value.eq_ignore_ascii_case("bayer4") || value.eq_ignore_ascii_case("bayer-4x4") {
+            Ok(Dither::Bayer4)
+        } else if value.eq_ignore_ascii_case("bayer8") || value.eq_ignore_ascii_case("bayer-8x8") {
+            Ok(Dither::Bayer8)
+        } else if value.eq_ignore_ascii_case("floyd-steinberg") || value.eq_ignore_ascii_case("floydsteinberg") {
+            Ok(Dither::FloydSteinberg)
+        } else if value.eq_ignore_ascii_case("atkinson") {
+            Ok(Dither::Atkinson)
+        } else if value.eq_ignore_ascii_case("sierra") {
+            Ok(Dither::Sierra)
+        } else if value.eq_ignore_ascii_case("blue-noise") || value.eq_ignore_ascii_case("bluenoise") {
+            Ok(Dither::BlueNoise)
+        } else {
+            Err(DitherParseError())
+        }
+    }
+}
+
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Quantizes `image` to `color_depth` like
+/// [`quantize_image`](crate::palette::quantize_image), but spreads the
+/// quantization error using the given dithering method.
+///
+/// Ordered methods (Bayer, blue noise) only depend on the pixel position,
+/// which keeps animations from flickering. Error diffusion looks better on
+/// still images, but any changed pixel changes everything after it.
+pub fn dither_image(image: &mut RgbaImage, color_depth: ColorDepth, dither: Dither, alpha_threshold: u8) {
+    let Some(palette) = Palette::for_color_depth(color_depth) else {
+        return;
+    };
+
+    let monochrome = color_depth.is_monochrome();
+    if let Some((matrix, size)) = threshold_matrix(dither) {
+        ordered(image, palette, monochrome, alpha_threshold, matrix, size);
+    } else if let Some((kernel, divisor)) = diffusion_kernel(dither) {
+        diffuse(image, palette, monochrome, alpha_threshold, kernel, divisor);
+    } else {
+        quantize_image(image, color_depth, alpha_threshold);
+    }
+}
+
+/// Like [`dither_image`], but quantizes to an arbitrary palette, e.g. one
+/// built for the image itself.
+pub fn dither_to_palette(image: &mut RgbaImage, palette: &Palette, dither: Dither, alpha_threshold: u8) {
+    if let Some((matrix, size)) = threshold_matrix(dither) {
+        ordered(image, palette, false, alpha_threshold, matrix, size);
+    } else if let Some((kernel, divisor)) = diffusion_kernel(dither) {
+        diffuse(image, palette, false, alpha_threshold, kernel, divisor);
+    } else {
+        for pixel in image.pixels_mut() {
+            let Rgba([r, g, b, a]) = *pixel;
+            if a >= alpha_threshold {
+                store_quantized(pixel, palette.quantize(Rgb([r, g, b])), false);
+            }
+        }
+    }
+}
+
+/// Decides which pixels of `image` are lit, i.e. have a luminance of at
+/// least `threshold`, dithering the luminance with the given method.
+/// Transparent pixels are never lit. Returns one entry per pixel, row by
+/// row.
+///
+/// Error diffusion doesn't cross the borders of blocks of `block_size`
+/// pixels, so that each block only depends on its own pixels.
+pub fn dither_mask(image: &RgbaImage, alpha_threshold: u8, threshold: u8, dither: Dither, block_size: (u32, u32)) -> Vec<bool> {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+    let block_width = block_size.0.max(1) as usize;
+    let block_height = block_size.1.max(1) as usize;
+    let threshold = threshold as f32;
+    let mut mask = vec![false; width * height];
+
+    if let Some((matrix, size)) = threshold_matrix(dither) {
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if pixel[3] >= alpha_threshold {
+                let offset = (matrix[(y as usize % size) * size + (x as usize % size)] - 0.5) * 255.0;
+                mask[y as usize * width + x as usize] = luminance(*pixel) + offset >= threshold;
+            }
+        }
+    } else if let Some((kernel, divisor)) = diffusion_kernel(dither) {
+        let mut errors = vec![0.0f32; width * 3];
+        for y in 0..height {
+            let reverse = y % 2 == 1;
+            for index in 0..width {
+                let x = if reverse { width - 1 - index } else { index };
+                let error = std::mem::take(&mut errors[(y % 3) * width + x]);
+
+                let pixel = *image.get_pixel(x as u32, y as u32);
+                if pixel[3] < alpha_threshold {
+                    continue;
+                }
+
+                let wanted = luminance(pixel) + error;
+                let lit = wanted >= threshold;
+                mask[y * width + x] = lit;
+
+                let diff = wanted - if lit { 255.0 } else { 0.0 };
+                for &(dx, dy, weight) in kernel {
+                    let nx = x as i64 + if reverse { -dx } else { dx } as i64;
+                    let ny = y + dy as usize;
+                    if nx < 0 || nx >= width as i64 || ny >= height {
+                        continue;
+                    }
+                    let nx = nx as usize;
+                    if nx / block_width == x / block_width && ny / block_height == y / block_height {
+                        errors[(ny % 3) * width + nx] += diff * weight / divisor;
+                    }
+                }
+            }
+        }
+    } else {
+        for (x, y, pixel) in image.enumerate_pixels() {
+            if pixel[3] >= alpha_threshold {
+                mask[y as usize * width + x as usize] = luminance(*pixel) >= threshold;
+            }
+        }
+    }
+
+    mask
+}
+
+#[inline]
+pub(crate) fn luminance(Rgba([r, g, b, _]): Rgba<u8>) -> f32 {
+    0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32
+}
+
+fn threshold_matrix(dither: Dither) -> Option<(&'static [f32], usize)> {
+    match dither {
+        Dither::Bayer2    => Some((bayer_matrix(1), 2)),
+        Dither::Bayer4    => Some((bayer_matrix(2), 4)),
+        Dither::Bayer8    => Some((bayer_matrix(3), 8)),
+        Dither::BlueNoise => Some((blue_noise(), BLUE_NOISE_SIZE)),
+        _ => None,
+    }
+}
+
+fn diffusion_kernel(dither: Dither) -> Option<(&'static Kernel, f32)> {
+    match dither {
+        Dither::FloydSteinberg => Some((&FLOYD_STEINBERG, 16.0)),
+        Dither::Atkinson => Some((&ATKINSON, 8.0)),
+        Dither::Sierra   => Some((&SIERRA, 32.0)),
+        _ => None,
+    }
+}
+
+fn ordered(image: &mut RgbaImage, palette: &Palette, monochrome: bool, alpha_threshold: u8, matrix: &[f32], size: usize) {
+    let spread = palette.spread();
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let Rgba([r, g, b, a]) = *pixel;
+        if a < alpha_threshold {
+            continue;
+        }
+        let threshold = matrix[(y as usize % size) * size + (x as usize % size)];
+        let offset = (threshold - 0.5) * spread;
+        let color = Rgb([
+            (r as f32 + offset).round().clamp(0.0, 255.0) as u8,
+            (g as f32 + offset).round().clamp(0.0, 255.0) as u8,
+            (b as f32 + offset).round().clamp(0.0, 255.0) as u8,
+        ]);
+        store_quantized(pixel, palette.quantize(color), monochrome);
+    }
+}
+
+/// `(dx, dy, weight)` triples of an error diffusion kernel.
+type Kernel = [(i32, u32, f32)];
+
+const FLOYD_STEINBERG: [(i32, u32, f32); 4] = [
+    (1, 0, 7.0),
+    (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0),
+];
+
+const ATKINSON: [(i32, u32, f32); 6] = [
+    (1, 0, 1.0), (2, 0, 1.0),
+    (-1, 1, 1.0), (0, 1, 1.0), (1, 1, 1.0),
+    (0, 2, 1.0),
+];
+
+const SIERRA: [(i32, u32, f32); 10] = [
+    (1, 0, 5.0), (2, 0, 3.0),
+    (-2, 1, 2.0), (-1, 1, 4.0), (0, 1, 5.0), (1, 1, 4.0), (2, 1, 2.0),
+    (-1, 2, 2.0), (0, 2, 3.0), (1, 2, 2.0),
+];
+
+fn diffuse(image: &mut RgbaImage, palette: &Palette, monochrome: bool, alpha_threshold: u8, kernel: &Kernel, divisor: f32) {
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+
+    // Errors of the next three rows (the biggest kernel reaches two rows
+    // down), used as a ring buffer.
+    let mut errors = vec![[0.0f32; 3]; width * 3];
+
+    for y in 0..height {
+        // Serpentine scanning avoids the diagonal artifacts of always
+        // pushing the error into the same direction.
+        let reverse = y % 2 == 1;
+        for index in 0..width {
+            let x = if reverse { width - 1 - index } else { index };
+            let row = (y % 3) * width;
+            let error = std::mem::take(&mut errors[row + x]);
+
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            let Rgba([r, g, b, a]) = *pixel;
+            if a < alpha_threshold {
+                continue;
+            }
+
+            let wanted = [
+                (r as f32 + error[0]).clamp(0.0, 255.0),
+                (g as f32 + error[1]).clamp(0.0, 255.0),
+                (b as f32 + error[2]).clamp(0.0, 255.0),
+            ];
+            let color = palette.quantize(Rgb([
+                wanted[0].round() as u8,
+                wanted[1].round() as u8,
+                wanted[2].round() as u8,
+            ]));
+            store_quantized(pixel, color, monochrome);
+
+            let Rgb([qr, qg, qb]) = color;
+            let diff = [wanted[0] - qr as f32, wanted[1] - qg as f32, wanted[2] - qb as f32];
+
+            for &(dx, dy, weight) in kernel {
+                let dx = if reverse { -dx } else { dx };
+                let nx = x as i64 + dx as i64;
+                let ny = y + dy as usize;
+                if nx < 0 || nx >= width as i64 || ny >= height {
+                    continue;
+                }
+                let target = &mut errors[(ny % 3) * width + nx as usize];
+                let factor = weight / divisor;
+                target[0] += diff[0] * factor;
+                target[1] += diff[1] * factor;
+                target[2] += diff[2] * factor;
+            }
+        }
+    }
+}
+
+/// Normalized thresholds of a `2^level` x `2^level` Bayer matrix.
+fn bayer_matrix(level: u32) -> &'static [f32] {
+    static MATRICES: [OnceLock<Vec<f32>>; 3] = [OnceLock::new(), OnceLock::new(), OnceLock::new()];
+
+    MATRICES[level as usize - 1].get_or_init(|| {
+        let size = 1usize << level;
+        let count = (size * size) as f32;
+        let mut matrix = Vec::with_capacity(size * size);
+        for y in 0..size {
+            for x in 0..size {
+                // Interleave the bits of x ^ y and y, in reverse order.
+                let xc = x ^ y;
+                let mut value = 0;
+                for bit in 0..level {
+                    value |= ((xc >> bit) & 1) << (2 * (level - 1 - bit) + 1);
+                    value |= ((y >> bit) & 1) << (2 * (level - 1 - bit));
+                }
+                matrix.push((value as f32 + 0.5) / count);
+            }
+        }
+        matrix
+    })
+}
+
+/// Normalized thresholds of a tileable blue noise texture, generated once
+/// with the void-and-cluster method.
+fn blue_noise() -> &'static [f32] {
+    static TEXTURE: OnceLock<Vec<f32>> = OnceLock::new();
+    TEXTURE.get_or_init(|| {
+        let ranks = void_and_cluster(BLUE_NOISE_SIZE);
+        let count = ranks.len() as f32;
+        ranks.into_iter().map(|rank| (rank as f32 + 0.5) / count).collect()
+    })
+}
+
+#[derive(Clone)]
+struct Energy {
+    size: usize,
+    radius: usize,
+    kernel: Vec<f32>,
+    values: Vec<f32>,
+}
+
+impl Energy {
+    fn new(size: usize, sigma: f32) -> Self {
+        let radius = (sigma * 4.0).ceil() as usize;
+        let side = radius * 2 + 1;
+        let mut kernel = Vec::with_capacity(side * side);
+        for dy in 0..side {
+            for dx in 0..side {
+                let fx = dx as f32 - radius as f32;
+                let fy = dy as f32 - radius as f32;
+                kernel.push((-(fx * fx + fy * fy) / (2.0 * sigma * sigma)).exp());
+            }
+        }
+        Self { size, radius, kernel, values: vec![0.0; size * size] }
+    }
+
+    fn update(&mut self, index: usize, sign: f32) {
+        let size = self.size;
+        let side = self.radius * 2 + 1;
+        let x0 = index % size;
+        let y0 = index / size;
+        for dy in 0..side {
+            let y = (y0 + size + dy - self.radius) % size;
+            for dx in 0..side {
+                let x = (x0 + size + dx - self.radius) % size;
+                self.values[y * size + x] += sign * self.kernel[dy * side + dx];
+            }
+        }
+    }
+
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        let mut best = 0;
+        let mut best_value = f32::NEG_INFINITY;
+        for (index, &value) in self.values.iter().enumerate() {
+            if pattern[index] && value > best_value {
+                best_value = value;
+                best = index;
+            }
+        }
+        best
+    }
+
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        let mut best = 0;
+        let mut best_value = f32::INFINITY;
+        for (index, &value) in self.values.iter().enumerate() {
+            if !pattern[index] && value < best_value {
+                best_value = value;
+                best = index;
+            }
+        }
+        best
+    }
+}
+
+fn void_and_cluster(size: usize) -> Vec<u32> {
+    let count = size * size;
+    let mut pattern = vec![false; count];
+    let mut energy = Energy::new(size, 1.5);
+
+    // Deterministic initial random pattern with about 10% of the pixels set.
+    let mut seed: u32 = 0x2545_f491;
+    let initial = count / 10;
+    let mut placed = 0;
+    while placed < initial {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        let index = seed as usize % count;
+        if !pattern[index] {
+            pattern[index] = true;
+            energy.update(index, 1.0);
+            placed += 1;
+        }
+    }
+
+    // Move points from the tightest clusters into the largest voids until
+    // that doesn't change anything anymore. It settles after about 150
+    // swaps, the limit only guards against ties making it cycle.
+    for _ in 0..count {
+        let cluster = energy.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        energy.update(cluster, -1.0);
+
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.update(void, 1.0);
+
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0u32; count];
+
+    // Rank the initial points by removing them tightest cluster first.
+    {
+        let mut pattern = pattern.clone();
+        let mut energy = energy.clone();
+        for rank in (0..initial).rev() {
+            let cluster = energy.tightest_cluster(&pattern);
+            pattern[cluster] = false;
+            energy.update(cluster, -1.0);
+            ranks[cluster] = rank as u32;
+        }
+    }
+
+    // Then fill up the largest voids.
+    for rank in initial..count {
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.update(void, 1.0);
+        ranks[void] = rank as u32;
+    }
+
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranks(matrix: &[f32]) -> Vec<usize> {
+        let count = matrix.len() as f32;
+        let mut ranks: Vec<usize> = matrix.iter().map(|&value| (value * count - 0.5).round() as usize).collect();
+        ranks.sort_unstable();
+        ranks
+    }
+
+    fn gray(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn bayer_matrices() {
+        assert_eq!(bayer_matrix(1), &[0.125, 0.625, 0.875, 0.375]);
+        for level in 1..=3 {
+            let size = 1usize << level;
+            assert_eq!(ranks(bayer_matrix(level)), (0..size * size).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn blue_noise_is_a_permutation() {
+        let texture = blue_noise();
+        assert_eq!(texture.len(), BLUE_NOISE_SIZE * BLUE_NOISE_SIZE);
+        assert_eq!(ranks(texture), (0..texture.len()).collect::<Vec<_>>());
+
+        let again = void_and_cluster(BLUE_NOISE_SIZE);
+        let count = again.len() as f32;
+        assert!(again.iter().zip(texture).all(|(&rank, &value)| (rank as f32 + 0.5) / count == value));
+    }
+
+    #[test]
+    fn blue_noise_has_no_clusters() {
+        // The first tenth of the thresholds are spread out, so no two of them
+        // are direct neighbours.
+        let texture = blue_noise();
+        let size = BLUE_NOISE_SIZE;
+        let lit = |x: usize, y: usize| texture[(y % size) * size + x % size] < 0.1;
+        for y in 0..size {
+            for x in 0..size {
+                if lit(x, y) {
+                    assert!(!lit(x + 1, y) && !lit(x, y + 1), "{x} {y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn kernel_weights_sum_to_divisor() {
+        for dither in [Dither::FloydSteinberg, Dither::Atkinson, Dither::Sierra] {
+            let (kernel, divisor) = diffusion_kernel(dither).unwrap();
+            let sum: f32 = kernel.iter().map(|&(_, _, weight)| weight).sum();
+            // Atkinson only passes on 3/4 of the error on purpose.
+            let expected = if dither == Dither::Atkinson { divisor * 0.75 } else { divisor };
+            assert_eq!(sum, expected, "{dither}");
+            assert!(kernel.iter().all(|&(dx, dy, _)| dy > 0 || dx > 0), "{dither}");
+        }
+    }
+
+    #[test]
+    fn mask_of_dark_gray() {
+        let image = gray(16, 16, 64);
+        let count = |dither| dither_mask(&image, 127, 128, dither, (16, 16)).into_iter().filter(|&lit| lit).count();
+
+        assert_eq!(count(Dither::None), 0);
+        assert_eq!(count(Dither::Bayer4), 64);
+        for dither in [Dither::FloydSteinberg, Dither::Sierra, Dither::BlueNoise] {
+            let lit = count(dither);
+            assert!((54..=74).contains(&lit), "{dither}: {lit}");
+        }
+    }
+
+    #[test]
+    fn mask_blocks_are_independent() {
+        let mut image = gray(8, 8, 100);
+        let before = dither_mask(&image, 127, 128, Dither::FloydSteinberg, (2, 4));
+        for x in 0..4 {
+            image.put_pixel(x, 0, Rgba([200, 200, 200, 255]));
+        }
+        let after = dither_mask(&image, 127, 128, Dither::FloydSteinberg, (2, 4));
+
+        for y in 0..8 {
+            for x in 0..8 {
+                if x >= 4 || y >= 4 {
+                    assert_eq!(before[y * 8 + x], after[y * 8 + x], "{x} {y}");
+                }
+            }
+        }
+        assert_ne!(before[..32], after[..32]);
+    }
+
+    #[test]
+    fn mask_skips_transparent_pixels() {
+        let mut image = gray(4, 4, 255);
+        image.put_pixel(1, 2, Rgba([255, 255, 255, 0]));
+        for dither in [Dither::None, Dither::Bayer2, Dither::FloydSteinberg] {
+            let mask = dither_mask(&image, 127, 128, dither, (4, 4));
+            assert_eq!(mask.iter().filter(|&&lit| lit).count(), 15, "{dither}");
+            assert!(!mask[2 * 4 + 1]);
+        }
+    }
+
+    #[test]
+    fn diffusion_keeps_the_average() {
+        let palette = Palette::new(vec![Rgb([0, 0, 0]), Rgb([255, 255, 255])], 0);
+        for dither in [Dither::FloydSteinberg, Dither::Atkinson, Dither::Sierra] {
+            let mut image = gray(32, 32, 96);
+            dither_to_palette(&mut image, &palette, dither, 127);
+            assert!(image.pixels().all(|pixel| pixel[0] == 0 || pixel[0] == 255));
+            let white = image.pixels().filter(|pixel| pixel[0] == 255).count() as f32;
+            let expected = 1024.0 * 96.0 / 255.0;
+            assert!((white - expected).abs() < 1024.0 * 0.04, "{dither}: {white}");
+        }
+    }
+
+    #[test]
+    fn ordered_dithering_mixes_colors() {
+        let palette = Palette::new(vec![Rgb([0, 0, 0]), Rgb([255, 255, 255])], 0);
+        for dither in [Dither::Bayer2, Dither::Bayer8, Dither::BlueNoise] {
+            let mut image = gray(128, 128, 96);
+            dither_to_palette(&mut image, &palette, dither, 127);
+            let white = image.pixels().filter(|pixel| pixel[0] == 255).count();
+            let black = image.pixels().filter(|pixel| pixel[0] == 0).count();
+            assert_eq!(white + black, 128 * 128, "{dither}");
+            assert!(white > 0 && black > 0, "{dither}");
+
+            // Only the position decides, so the pattern repeats.
+            for (x, y) in [(0, 0), (5, 17), (63, 30)] {
+                assert_eq!(image.get_pixel(x, y), image.get_pixel(x + 64, y + 64), "{dither}");
+            }
+        }
+    }
+
+    #[test]
+    fn undithered_colors_are_quantized() {
+        let palette = Palette::new(vec![Rgb([0, 0, 0]), Rgb([255, 255, 255])], 0);
+        let mut image = gray(4, 4, 96);
+        dither_to_palette(&mut image, &palette, Dither::None, 127);
+        assert!(image.pixels().all(|pixel| *pixel == Rgba([0, 0, 0, 255])));
+    }
+}
@@ -0,0 +1,215 @@
+use std::{fmt::Display, str::FromStr};
+use std::sync::Arc;
+
+use image::RgbaImage;
+
+use crate::ascii::AsciiEncoder;
+use crate::blocks::BlockEncoder;
+use crate::braille::BrailleEncoder;
+use crate::cells::{cells_to_ansi_into, CellEncoder};
+use crate::image_to_ansi::{cursor_to_last_row, image_to_ansi_into};
+use crate::iterm2::image_to_iterm2_into;
+use crate::kitty::KittyImage;
+use crate::palette::Palette;
+use crate::sixel::image_to_sixel_into;
+use crate::term;
+
+use crate::color_depth::ColorDepth;
+use crate::dither::Dither;
+use crate::ramp::Ramp;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Encoder {
+    #[default]
+    HalfBlock,
+    Quadrant,
+    Sextant,
+    Braille,
+    Ascii,
+    AsciiEdges,
+    Sixel,
+    Kitty,
+    Iterm2,
+}
+
+/// Settings that are passed on to the encoders.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodeOptions {
+    pub alpha_threshold: u8,
+    pub color_depth: ColorDepth,
+    pub dither: Dither,
+    /// Luminance at which a braille dot is lit.
+    pub threshold: u8,
+    /// Characters of the ASCII encoders, from dark to bright.
+    pub ramp: Ramp,
+    /// Compress the image data of the kitty graphics protocol.
+    pub compress: bool,
+}
+
+impl Default for EncodeOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            alpha_threshold: 127,
+            color_depth: ColorDepth::TrueColor,
+            dither: Dither::Auto,
+            threshold: 128,
+            ramp: Ramp::default(),
+            compress: false,
+        }
+    }
+}
+
+/// What the encoders carry over from one frame to the next. It belongs to
+/// the previous frame that is passed to [`Encoder::encode_into`], so it has
+/// to be reset whenever that starts over.
+#[derive(Debug, Clone, Default)]
+pub struct EncoderState {
+    /// Palette of the last sixel image.
+    sixel_palette: Option<Arc<Palette>>,
+    /// The kitty image that is updated with every frame.
+    kitty: Option<KittyImage>,
+}
+
+impl Encoder {
+    /// Width and height in pixels of the image area covered by one
+    /// character cell. For graphics protocols this is the terminal's cell
+    /// size in pixels.
+    #[inline]
+    pub fn cell_size(&self) -> (u32, u32) {
+        match self {
+            Encoder::HalfBlock => (1, 2),
+            Encoder::Quadrant  => (2, 2),
+            Encoder::Sextant   => (2, 3),
+            Encoder::Braille   => (2, 4),
+            Encoder::Ascii | Encoder::AsciiEdges => (AsciiEncoder::CELL_WIDTH, AsciiEncoder::CELL_HEIGHT),
+            Encoder::Sixel | Encoder::Kitty | Encoder::Iterm2 => term::cell_pixel_size().unwrap_or(term::DEFAULT_CELL_PIXEL_SIZE),
+        }
+    }
+
+    /// Whether the image is drawn with a terminal graphics protocol instead
+    /// of characters.
+    #[inline]
+    pub fn is_graphics(&self) -> bool {
+        matches!(self, Encoder::Sixel | Encoder::Kitty | Encoder::Iterm2)
+    }
+
+    /// Whether animations are uploaded once and played by the terminal.
+    #[inline]
+    pub fn has_native_animation(&self) -> bool {
+        matches!(self, Encoder::Kitty)
+    }
+
+    /// Whether the pixels need to be quantized (and dithered) to the color
+    /// depth before encoding. Braille and ASCII pick their glyphs from the
+    /// luminance of the original pixels and only map the resulting cell
+    /// colors to the palette. Sixel builds its own palette, kitty and iTerm2
+    /// send true color.
+    #[inline]
+    pub fn quantizes_pixels(&self) -> bool {
+        !matches!(self, Encoder::Braille | Encoder::Ascii | Encoder::AsciiEdges | Encoder::Sixel | Encoder::Kitty | Encoder::Iterm2)
+    }
+
+    /// Renders `image` into `lines`, only emitting the cells that differ
+    /// from `prev_frame`.
+    pub fn encode_into(&self, prev_frame: &RgbaImage, image: &RgbaImage, full_width: bool, options: &EncodeOptions, state: &mut EncoderState, lines: &mut String) {
+        let &EncodeOptions { alpha_threshold, color_depth, dither, threshold, ref ramp, compress } = options;
+        match self {
+            Encoder::HalfBlock => image_to_ansi_into(prev_frame, image, alpha_threshold, full_width, color_depth, lines),
+            Encoder::Quadrant  => cells_to_ansi_into(&BlockEncoder::QUADRANT, prev_frame, image, alpha_threshold, full_width, color_depth, lines),
+            Encoder::Sextant   => cells_to_ansi_into(&BlockEncoder::SEXTANT, prev_frame, image, alpha_threshold, full_width, color_depth, lines),
+            Encoder::Braille   => {
+                let braille = BrailleEncoder::new(image, alpha_threshold, threshold, dither.resolve(false));
+                cells_to_ansi_into(&braille, prev_frame, image, alpha_threshold, full_width, color_depth, lines);
+            }
+            Encoder::Ascii | Encoder::AsciiEdges => {
+                let ascii = AsciiEncoder::new(image, alpha_threshold, ramp.chars(), *self == Encoder::AsciiEdges);
+                cells_to_ansi_into(&ascii, prev_frame, image, alpha_threshold, full_width, color_depth, lines);
+            }
+            Encoder::Sixel => image_to_sixel_into(prev_frame, image, alpha_threshold, dither.resolve(false), self.cell_size(), &mut state.sixel_palette, lines),
+            Encoder::Kitty => {
+                state.kitty
+                    .get_or_insert_with(|| KittyImage::new(KittyImage::default_id(), compress))
+                    .update(image, lines);
+                cursor_to_last_row(image.height().div_ceil(self.cell_size().1), lines);
+            }
+            Encoder::Iterm2 => {
+                image_to_iterm2_into(prev_frame, image, self.cell_size(), lines);
+                cursor_to_last_row(image.height().div_ceil(self.cell_size().1), lines);
+            }
+        }
+    }
+
+    /// Calls `f` with the cell encoder for `image`, or returns `None` for
+    /// graphics protocols, which don't work with character cells.
+    pub fn with_cell_encoder<R>(&self, image: &RgbaImage, options: &EncodeOptions, f: impl FnOnce(&dyn CellEncoder) -> R) -> Option<R> {
+        match self {
+            Encoder::HalfBlock => Some(f(&BlockEncoder::HALF)),
+            Encoder::Quadrant  => Some(f(&BlockEncoder::QUADRANT)),
+            Encoder::Sextant   => Some(f(&BlockEncoder::SEXTANT)),
+            Encoder::Braille   => Some(f(&BrailleEncoder::new(image, options.alpha_threshold, options.threshold, options.dither.resolve(false)))),
+            Encoder::Ascii | Encoder::AsciiEdges => {
+                let ascii = AsciiEncoder::new(image, options.alpha_threshold, options.ramp.chars(), *self == Encoder::AsciiEdges);
+                Some(f(&ascii))
+            }
+            Encoder::Sixel | Encoder::Kitty | Encoder::Iterm2 => None,
+        }
+    }
+}
+
+impl Display for Encoder {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HalfBlock => "half-block",
+            Self::Quadrant  => "quadrant",
+            Self::Sextant   => "sextant",
+            Self::Braille   => "braille",
+            Self::Ascii     => "ascii",
+            Self::AsciiEdges => "ascii-edges",
+            Self::Sixel     => "sixel",
+            Self::Kitty     => "kitty",
+            Self::Iterm2    => "iterm2",
+        }.fmt(f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct EncoderParseError();
+
+impl Display for EncoderParseError {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        "illegal encoder".fmt(f)
+    }
+}
+
+impl std::error::Error for EncoderParseError {}
+
+impl FromStr for Encoder {
+    type Err = EncoderParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.eq_ignore_ascii_case("half-block") || value.eq_ignore_ascii_case("halfblock") {
+            Ok(Encoder::HalfBlock)
+        } else if value.eq_ignore_ascii_case("quadrant") {
+            Ok(Encoder::Quadrant)
+        } else if value.eq_ignore_ascii_case("sextant") {
+            Ok(Encoder::Sextant)
+        } else if value.eq_ignore_ascii_case("braille") {
+            Ok(Encoder::Braille)
+        } else if value.eq_ignore_ascii_case("ascii") {
+            Ok(Encoder::Ascii)
+        } else if value.eq_ignore_ascii_case("ascii-edges") || value.eq_ignore_ascii_case("asciiedges") {
+            Ok(Encoder::AsciiEdges)
+        } else if value.eq_ignore_ascii_case("sixel") {
+            Ok(Encoder::Sixel)
+        } else if value.eq_ignore_ascii_case("kitty") {
+            Ok(Encoder::Kitty)
+        } else if value.eq_ignore_ascii_case("iterm2") || value.eq_ignore_ascii_case("iterm") {
+            Ok(Encoder::Iterm2)
+        } else {
+            Err(EncoderParseError())
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::fmt::Write;
+
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::cells::{Cell, CellEncoder};
+use crate::color_depth::ColorDepth;
+use crate::palette::Palette;
+
+/// Colors of a run of cells. `None` means the default color of the page.
+type RunStyle = (Option<Rgb<u8>>, Option<Rgb<u8>>);
+
+#[inline]
+fn push_escaped(text: &str, html: &mut String) {
+    for ch in text.chars() {
+        push_escaped_char(ch, html);
+    }
+}
+
+#[inline]
+fn push_escaped_char(ch: char, html: &mut String) {
+    match ch {
+        '&' => html.push_str("&amp;"),
+        '<' => html.push_str("&lt;"),
+        '>' => html.push_str("&gt;"),
+        '"' => html.push_str("&quot;"),
+        ch  => html.push(ch),
+    }
+}
+
+/// The colors `cell` shows at `color_depth`, mapped to the palette entries
+/// the terminal would use.
+fn cell_style(cell: &Cell, color_depth: ColorDepth) -> RunStyle {
+    if color_depth == ColorDepth::Monochrome {
+        // Lit pixels use the default foreground color, like in the terminal.
+        return (None, None);
+    }
+
+    let palette = Palette::for_color_depth(color_depth);
+    let color = |color: Option<Rgba<u8>>| color.map(|Rgba([r, g, b, _])| {
+        let rgb = Rgb([r, g, b]);
+        palette.map_or(rgb, |palette| palette.quantize(rgb))
+    });
+
+    let fg = if cell.needs_fg() { color(cell.fg) } else { None };
+    let bg = if cell.needs_bg() { color(cell.bg) } else { None };
+    (fg, bg)
+}
+
+fn open_span(style: RunStyle, color_depth: ColorDepth, classes: bool, html: &mut String) {
+    let (fg, bg) = style;
+    let palette = if classes { Palette::for_color_depth(color_depth) } else { None };
+
+    if let Some(palette) = palette {
+        html.push_str("<span class=\"");
+        if let Some(fg) = fg {
+            let _ = write!(html, "ansi-fg-{}", palette.terminal_index(fg));
+        }
+        if let Some(bg) = bg {
+            if fg.is_some() {
+                html.push(' ');
+            }
+            let _ = write!(html, "ansi-bg-{}", palette.terminal_index(bg));
+        }
+    } else {
+        html.push_str("<span style=\"");
+        if let Some(Rgb([r, g, b])) = fg {
+            let _ = write!(html, "color:#{r:02x}{g:02x}{b:02x}");
+        }
+        if let Some(Rgb([r, g, b])) = bg {
+            if fg.is_some() {
+                html.push(';');
+            }
+            let _ = write!(html, "background:#{r:02x}{g:02x}{b:02x}");
+        }
+    }
+    html.push_str("\">");
+}
+
+/// Renders `image` with the given cell encoder as a `<pre>` element that
+/// looks like the output of
+/// [`cells_to_text_into`](crate::cells::cells_to_text_into). Neighbouring
+/// cells with the same colors share one `<span>`.
+///
+/// With `classes` the colors of palettes with 256, 16 or 8 colors are
+/// written as `ansi-fg-N` and `ansi-bg-N` classes, which are defined by
+/// [`palette_css_into`], instead of inline styles.
+pub fn cells_to_html_into<E: CellEncoder + ?Sized>(encoder: &E, image: &RgbaImage, alpha_threshold: u8, color_depth: ColorDepth, classes: bool, line_end: &str, html: &mut String) {
+    let (cell_width, cell_height) = encoder.cell_size();
+    let columns = image.width().div_ceil(cell_width);
+    let rows = image.height().div_ceil(cell_height);
+
+    html.clear();
+    html.push_str("<pre style=\"line-height:1\">");
+
+    for y in 0..rows {
+        let mut current: RunStyle = (None, None);
+        for x in 0..columns {
+            let cell = encoder.encode_cell(image, x, y, alpha_threshold);
+            let style = cell_style(&cell, color_depth);
+            if style != current {
+                if current != (None, None) {
+                    html.push_str("</span>");
+                }
+                if style != (None, None) {
+                    open_span(style, color_depth, classes, html);
+                }
+                current = style;
+            }
+            push_escaped_char(cell.ch, html);
+        }
+        if current != (None, None) {
+            html.push_str("</span>");
+        }
+        html.push_str(line_end);
+    }
+
+    html.push_str("</pre>");
+    html.push_str(line_end);
+}
+
+/// Writes the CSS rules for the classes [`cells_to_html_into`] uses for
+/// `color_depth`. Nothing is written for true color and monochrome.
+pub fn palette_css_into(color_depth: ColorDepth, line_end: &str, css: &mut String) {
+    if color_depth == ColorDepth::Monochrome {
+        return;
+    }
+    let Some(palette) = Palette::for_color_depth(color_depth) else {
+        return;
+    };
+
+    for &color in palette.colors() {
+        let index = palette.terminal_index(color);
+        let Rgb([r, g, b]) = color;
+        let _ = write!(css, ".ansi-fg-{index} {{ color: #{r:02x}{g:02x}{b:02x}; }}{line_end}");
+        let _ = write!(css, ".ansi-bg-{index} {{ background: #{r:02x}{g:02x}{b:02x}; }}{line_end}");
+    }
+}
+
+/// Writes the start of a standalone HTML page up to and including the
+/// `<body>` tag, with `css` in its stylesheet.
+pub fn page_start_into(title: &str, css: &str, line_end: &str, html: &mut String) {
+    let _ = write!(html, "<!DOCTYPE html>{line_end}<html>{line_end}<head>{line_end}<meta charset=\"utf-8\">{line_end}<title>");
+    push_escaped(title, html);
+    let _ = write!(html, "</title>{line_end}");
+    if !css.is_empty() {
+        let _ = write!(html, "<style>{line_end}{css}</style>{line_end}");
+    }
+    let _ = write!(html, "</head>{line_end}<body>{line_end}");
+}
+
+/// Writes the end of a page started with [`page_start_into`].
+pub fn page_end_into(line_end: &str, html: &mut String) {
+    let _ = write!(html, "</body>{line_end}</html>{line_end}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// One pixel per cell, showing the character of its column in the
+    /// pixel's color.
+    struct Chars(&'static [char]);
+
+    impl CellEncoder for Chars {
+        fn cell_size(&self) -> (u32, u32) {
+            (1, 1)
+        }
+
+        fn encode_cell(&self, image: &RgbaImage, x: u32, y: u32, alpha_threshold: u8) -> Cell {
+            let color = *image.get_pixel(x, y);
+            let fg = if color[3] < alpha_threshold { None } else { Some(color) };
+            Cell { ch: self.0[x as usize], fg, bg: None }
+        }
+    }
+
+    fn row(colors: &[[u8; 4]]) -> RgbaImage {
+        RgbaImage::from_fn(colors.len() as u32, 1, |x, _| Rgba(colors[x as usize]))
+    }
+
+    #[test]
+    fn runs_share_a_span() {
+        let image = row(&[[255, 0, 0, 255], [255, 0, 0, 255], [0, 0, 255, 255], [0, 0, 0, 0]]);
+        let mut html = String::new();
+        cells_to_html_into(&Chars(&['<', '&', '>', '"']), &image, 127, ColorDepth::TrueColor, false, "\n", &mut html);
+        assert_eq!(html, concat!(
+            "<pre style=\"line-height:1\">",
+            "<span style=\"color:#ff0000\">&lt;&amp;</span>",
+            "<span style=\"color:#0000ff\">&gt;</span>&quot;\n",
+            "</pre>\n",
+        ));
+    }
+
+    #[test]
+    fn palette_colors_use_classes() {
+        let palette = Palette::for_color_depth(ColorDepth::Xterm256).unwrap();
+        let red = palette.quantize(Rgb([250, 10, 10]));
+        let image = row(&[[250, 10, 10, 255], [255, 0, 0, 255], [0, 0, 0, 0]]);
+        let mut html = String::new();
+        cells_to_html_into(&Chars(&['a', 'b', 'c']), &image, 127, ColorDepth::Xterm256, true, "\n", &mut html);
+        assert_eq!(html, format!(
+            "<pre style=\"line-height:1\"><span class=\"ansi-fg-{}\">ab</span>c\n</pre>\n",
+            palette.terminal_index(red),
+        ));
+    }
+
+    #[test]
+    fn monochrome_has_no_spans() {
+        let image = row(&[[255, 0, 0, 255], [0, 0, 255, 255]]);
+        let mut html = String::new();
+        cells_to_html_into(&Chars(&['<', 'b']), &image, 127, ColorDepth::Monochrome, true, "\n", &mut html);
+        assert_eq!(html, "<pre style=\"line-height:1\">&lt;b\n</pre>\n");
+    }
+
+    #[test]
+    fn title_is_escaped() {
+        let mut html = String::new();
+        page_start_into("a<b & c", "", "\n", &mut html);
+        assert!(html.contains("<title>a&lt;b &amp; c</title>"), "{html}");
+        assert!(!html.contains("<style>"));
+    }
+}
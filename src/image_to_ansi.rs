@@ -1,16 +1,74 @@
 use std::fmt::Write;
 
-use image::{Rgba, RgbaImage};
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::color_depth::ColorDepth;
+use crate::palette::Palette;
 
 #[inline]
-pub fn image_to_ansi(prev_frame: &RgbaImage, image: &RgbaImage, alpha_threshold: u8, full_width: bool) -> String {
+pub fn image_to_ansi(prev_frame: &RgbaImage, image: &RgbaImage, alpha_threshold: u8, full_width: bool, color_depth: ColorDepth) -> String {
     let mut lines = String::new();
-    image_to_ansi_into(prev_frame, image, alpha_threshold, full_width, &mut lines);
+    image_to_ansi_into(prev_frame, image, alpha_threshold, full_width, color_depth, &mut lines);
     lines
 }
 
+fn write_color(color_depth: ColorDepth, background: bool, color: Rgba<u8>, lines: &mut String) {
+    let Rgba([r, g, b, _]) = color;
+    match color_depth {
+        ColorDepth::TrueColor | ColorDepth::Auto => {
+            let code = if background { 48 } else { 38 };
+            let _ = write!(lines, "\x1B[{code};2;{r};{g};{b}m");
+        }
+        ColorDepth::Xterm256 => {
+            let code = if background { 48 } else { 38 };
+            let index = terminal_index(color_depth, Rgb([r, g, b]));
+            let _ = write!(lines, "\x1B[{code};5;{index}m");
+        }
+        ColorDepth::Ansi16 | ColorDepth::Ansi8 => {
+            let index = terminal_index(color_depth, Rgb([r, g, b])) as u32;
+            let code = match (background, index < 8) {
+                (false, true)  => 30 + index,
+                (false, false) => 90 + index - 8,
+                (true,  true)  => 40 + index,
+                (true,  false) => 100 + index - 8,
+            };
+            let _ = write!(lines, "\x1B[{code}m");
+        }
+        ColorDepth::Monochrome => {
+            // Only lit pixels are left at this point and they use the
+            // terminal's default foreground color.
+        }
+    }
+}
+
+#[inline]
+fn terminal_index(color_depth: ColorDepth, color: Rgb<u8>) -> u8 {
+    Palette::for_color_depth(color_depth).map_or(0, |palette| palette.terminal_index(color))
+}
+
+#[inline]
+pub(crate) fn write_fg(color_depth: ColorDepth, color: Rgba<u8>, lines: &mut String) {
+    write_color(color_depth, false, color, lines);
+}
+
+#[inline]
+pub(crate) fn write_bg(color_depth: ColorDepth, color: Rgba<u8>, lines: &mut String) {
+    write_color(color_depth, true, color, lines);
+}
+
+/// Moves the cursor from the top-left corner of an image that is `rows`
+/// cells high to its last row, where the text encoders leave it. Used for
+/// graphics that don't move the cursor, so that whatever is printed next
+/// doesn't end up on top of the image.
+#[inline]
+pub fn cursor_to_last_row(rows: u32, lines: &mut String) {
+    if rows > 1 {
+        move_cursor(0, 0, 0, rows - 1, lines);
+    }
+}
+
 #[inline]
-fn move_cursor(curr_x: u32, curr_line_y: u32, x: u32, line_y: u32, lines: &mut String) {
+pub(crate) fn move_cursor(curr_x: u32, curr_line_y: u32, x: u32, line_y: u32, lines: &mut String) {
     if x != curr_x {
         if x > curr_x {
             let dx = x - curr_x;
@@ -48,10 +106,17 @@ fn move_cursor(curr_x: u32, curr_line_y: u32, x: u32, line_y: u32, lines: &mut S
     }
 }
 
-pub fn image_to_ansi_into(prev_frame: &RgbaImage, image: &RgbaImage, alpha_threshold: u8, full_width: bool, lines: &mut String) {
+/// Renders `image` using half block characters, only emitting the cells that
+/// differ from `prev_frame`.
+///
+/// Colors are written for the given `color_depth`. The image is expected to
+/// already be quantized to that color depth (see
+/// [`quantize_image`](crate::palette::quantize_image)), so that pixels that
+/// end up as the same terminal color also compare as equal.
+pub fn image_to_ansi_into(prev_frame: &RgbaImage, image: &RgbaImage, alpha_threshold: u8, full_width: bool, color_depth: ColorDepth, lines: &mut String) {
     let width = image.width();
     let line_len = (width as usize) * "\x1B[38;2;255;255;255\x1B[48;2;255;255;255m▄".len() + "\x1B[0m".len();
-    let row_count = (image.height() + 1) / 2;
+    let row_count = image.height().div_ceil(2);
 
     lines.clear();
 
@@ -72,17 +137,17 @@ pub fn image_to_ansi_into(prev_frame: &RgbaImage, image: &RgbaImage, alpha_thres
                 let color = *image.get_pixel(x, y);
                 if color != *prev_frame.get_pixel(x, y) {
                     move_cursor(curr_x, curr_line_y, x, line_y, lines);
-                    let Rgba([r, g, b, a]) = color;
-                    if a < alpha_threshold {
+                    if color[3] < alpha_threshold {
                         if prev_color[3] < alpha_threshold {
-                            lines.push_str(" ");
+                            lines.push(' ');
                         } else {
                             lines.push_str("\x1B[0m ");
                         }
                     } else if color == prev_color {
-                        lines.push_str("▀");
+                        lines.push('▀');
                     } else {
-                        let _ = write!(lines, "\x1B[38;2;{r};{g};{b}m▀");
+                        write_fg(color_depth, color, lines);
+                        lines.push('▀');
                     }
                     prev_color = color;
                     // NOTE: Cursor location doesn't update at the end of the screen.
@@ -104,57 +169,70 @@ pub fn image_to_ansi_into(prev_frame: &RgbaImage, image: &RgbaImage, alpha_thres
 
                 if color_top != *prev_frame.get_pixel(x, y) || color_bottom != *prev_frame.get_pixel(x, y + 1) {
                     move_cursor(curr_x, curr_line_y, x, line_y, lines);
-                    let Rgba([r1, g1, b1, a1]) = color_top;
+                    let a1 = color_top[3];
 
                     if color_top == color_bottom {
                         if a1 < alpha_threshold {
                             if prev_bg.0[3] < alpha_threshold && prev_fg.0[3] < alpha_threshold {
-                                lines.push_str(" ");
+                                lines.push(' ');
                             } else {
                                 lines.push_str("\x1B[0m ");
                             }
                         } else {
-                            let _ = write!(lines, "\x1B[38;2;{r1};{g1};{b1}m█");
+                            if prev_fg != color_top {
+                                write_fg(color_depth, color_top, lines);
+                            }
+                            lines.push('█');
                         }
                         prev_fg = color_top;
                         prev_bg = color_top;
                     } else {
-                        let Rgba([r2, g2, b2, a2]) = color_bottom;
+                        let a2 = color_bottom[3];
                         if a1 < alpha_threshold && a2 < alpha_threshold {
                             if prev_bg.0[3] < alpha_threshold && prev_fg.0[3] < alpha_threshold {
-                                lines.push_str(" ");
+                                lines.push(' ');
                             } else {
                                 lines.push_str("\x1B[0m ");
                             }
                             prev_fg = color_top;
                             prev_bg = color_bottom;
                         } else if a1 < alpha_threshold {
-                            let _ = write!(lines, "\x1B[0m\x1B[38;2;{r2};{g2};{b2}m▄");
+                            lines.push_str("\x1B[0m");
+                            write_fg(color_depth, color_bottom, lines);
+                            lines.push('▄');
                             prev_fg = color_bottom;
                             prev_bg = color_top;
                         } else if a2 < alpha_threshold {
-                            let _ = write!(lines, "\x1B[0m\x1B[38;2;{r1};{g1};{b1}m▀");
+                            lines.push_str("\x1B[0m");
+                            write_fg(color_depth, color_top, lines);
+                            lines.push('▀');
                             prev_fg = color_top;
                             prev_bg = color_bottom;
                         } else {
                             if prev_fg == color_bottom && prev_bg == color_top {
-                                let _ = write!(lines, "▄");
+                                lines.push('▄');
                             } else if prev_fg == color_top && prev_bg == color_bottom {
-                                let _ = write!(lines, "▀");
+                                lines.push('▀');
                             } else if prev_fg == color_bottom {
-                                let _ = write!(lines, "\x1B[48;2;{r1};{g1};{b1}m▄");
+                                write_bg(color_depth, color_top, lines);
+                                lines.push('▄');
                                 prev_bg = color_top;
                             } else if prev_fg == color_top {
-                                let _ = write!(lines, "\x1B[48;2;{r2};{g2};{b2}m▀");
+                                write_bg(color_depth, color_bottom, lines);
+                                lines.push('▀');
                                 prev_bg = color_bottom;
                             } else if prev_bg == color_top {
-                                let _ = write!(lines, "\x1B[38;2;{r2};{g2};{b2}m▄");
+                                write_fg(color_depth, color_bottom, lines);
+                                lines.push('▄');
                                 prev_fg = color_bottom;
                             } else if prev_bg == color_bottom {
-                                let _ = write!(lines, "\x1B[38;2;{r1};{g1};{b1}m▀");
+                                write_fg(color_depth, color_top, lines);
+                                lines.push('▀');
                                 prev_fg = color_top;
                             } else {
-                                let _ = write!(lines, "\x1B[48;2;{r1};{g1};{b1}m\x1B[38;2;{r2};{g2};{b2}m▄");
+                                write_bg(color_depth, color_top, lines);
+                                write_fg(color_depth, color_bottom, lines);
+                                lines.push('▄');
                                 prev_fg = color_bottom;
                                 prev_bg = color_top;
                             }
@@ -197,3 +275,66 @@ pub fn image_to_ansi_into(prev_frame: &RgbaImage, image: &RgbaImage, alpha_thres
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sgr(color_depth: ColorDepth, background: bool, color: [u8; 3]) -> String {
+        let [r, g, b] = color;
+        let mut lines = String::new();
+        write_color(color_depth, background, Rgba([r, g, b, 255]), &mut lines);
+        lines
+    }
+
+    #[test]
+    fn true_color_codes() {
+        assert_eq!(sgr(ColorDepth::TrueColor, false, [1, 2, 3]), "\x1B[38;2;1;2;3m");
+        assert_eq!(sgr(ColorDepth::TrueColor, true, [1, 2, 3]), "\x1B[48;2;1;2;3m");
+    }
+
+    #[test]
+    fn xterm_256_codes() {
+        assert_eq!(sgr(ColorDepth::Xterm256, false, [255, 0, 0]), "\x1B[38;5;196m");
+        assert_eq!(sgr(ColorDepth::Xterm256, true, [255, 0, 0]), "\x1B[48;5;196m");
+        assert_eq!(sgr(ColorDepth::Xterm256, false, [0, 0, 0]), "\x1B[38;5;16m");
+        assert_eq!(sgr(ColorDepth::Xterm256, false, [120, 120, 120]), "\x1B[38;5;243m");
+    }
+
+    #[test]
+    fn ansi_16_codes() {
+        assert_eq!(sgr(ColorDepth::Ansi16, false, [205, 0, 0]), "\x1B[31m");
+        assert_eq!(sgr(ColorDepth::Ansi16, true, [205, 0, 0]), "\x1B[41m");
+        assert_eq!(sgr(ColorDepth::Ansi16, false, [255, 0, 0]), "\x1B[91m");
+        assert_eq!(sgr(ColorDepth::Ansi16, true, [255, 0, 0]), "\x1B[101m");
+        assert_eq!(sgr(ColorDepth::Ansi16, false, [255, 255, 255]), "\x1B[97m");
+    }
+
+    #[test]
+    fn ansi_8_codes() {
+        assert_eq!(sgr(ColorDepth::Ansi8, false, [255, 0, 0]), "\x1B[31m");
+        assert_eq!(sgr(ColorDepth::Ansi8, true, [0, 0, 238]), "\x1B[44m");
+        assert_eq!(sgr(ColorDepth::Ansi8, false, [255, 255, 255]), "\x1B[37m");
+    }
+
+    #[test]
+    fn monochrome_has_no_codes() {
+        assert_eq!(sgr(ColorDepth::Monochrome, false, [255, 255, 255]), "");
+        assert_eq!(sgr(ColorDepth::Monochrome, true, [255, 255, 255]), "");
+    }
+
+    #[test]
+    fn reduced_colors_are_deduplicated() {
+        let mut image = RgbaImage::new(3, 2);
+        for (x, color) in [[250, 2, 3], [255, 0, 0], [0, 0, 255]].into_iter().enumerate() {
+            let [r, g, b] = color;
+            image.put_pixel(x as u32, 0, Rgba([r, g, b, 255]));
+            image.put_pixel(x as u32, 1, Rgba([r, g, b, 255]));
+        }
+        crate::palette::quantize_image(&mut image, ColorDepth::Xterm256, 127);
+
+        let prev_frame = RgbaImage::new(3, 2);
+        let lines = image_to_ansi(&prev_frame, &image, 127, false, ColorDepth::Xterm256);
+        assert_eq!(lines, "\x1B[38;5;196m██\x1B[38;5;21m█\x1B[0m");
+    }
+}
@@ -0,0 +1,258 @@
+use std::time::Duration;
+
+/// A key press read from the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Enter,
+    Backspace,
+    Escape,
+}
+
+/// Why waiting for a key press ended without one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitError {
+    /// The wait was interrupted by a signal, e.g. Ctrl+C.
+    Interrupted,
+    /// There is nothing to read anymore.
+    Closed,
+}
+
+/// Reads single key presses from a terminal, which is put into
+/// non-canonical mode without echo for as long as this exists.
+#[cfg(target_family = "unix")]
+pub struct Input {
+    fd: libc::c_int,
+    orig_term: libc::termios,
+    buf: Vec<u8>,
+    owned: bool,
+}
+
+#[cfg(target_family = "unix")]
+impl Input {
+    /// Takes over the terminal `fd`. Returns `None` if it isn't a terminal.
+    pub fn new(fd: libc::c_int) -> Option<Self> {
+        let orig_term = crate::term::get_termios(fd)?;
+        let mut term = orig_term;
+        term.c_lflag &= !(libc::ICANON | libc::ECHO);
+        term.c_cc[libc::VMIN] = 0;
+        term.c_cc[libc::VTIME] = 0;
+        if !crate::term::set_termios(fd, &term) {
+            return None;
+        }
+        Some(Self { fd, orig_term, buf: Vec::new(), owned: false })
+    }
+
+    /// Opens the controlling terminal, for when stdin isn't one.
+    pub fn tty() -> Option<Self> {
+        let fd = unsafe { libc::open(c"/dev/tty".as_ptr(), libc::O_RDONLY | libc::O_NOCTTY | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return None;
+        }
+        let Some(mut input) = Self::new(fd) else {
+            unsafe { libc::close(fd); }
+            return None;
+        };
+        input.owned = true;
+        Some(input)
+    }
+
+    /// Waits up to `timeout` for a key press. Returns `Ok(None)` if no key
+    /// was pressed in time.
+    pub fn wait_key(&mut self, timeout: Duration) -> Result<Option<Key>, WaitError> {
+        if let Some(key) = self.parse_key() {
+            return Ok(Some(key));
+        }
+
+        let mut pollfd = libc::pollfd { fd: self.fd, events: libc::POLLIN, revents: 0 };
+        let millis = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let ready = unsafe { libc::poll(&mut pollfd, 1, millis) };
+        if ready < 0 {
+            return Err(WaitError::Interrupted);
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+
+        let mut buf = [0u8; 64];
+        let count = unsafe { libc::read(self.fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if count < 0 {
+            return Err(WaitError::Interrupted);
+        }
+        if count == 0 {
+            return Err(WaitError::Closed);
+        }
+        self.buf.extend_from_slice(&buf[..count as usize]);
+
+        Ok(self.parse_key())
+    }
+
+    /// Takes the first key press out of the buffer. Unknown escape
+    /// sequences are skipped.
+    fn parse_key(&mut self) -> Option<Key> {
+        while !self.buf.is_empty() {
+            let (key, len) = parse_key(&self.buf);
+            self.buf.drain(..len);
+            if key.is_some() {
+                return key;
+            }
+        }
+        None
+    }
+}
+
+#[cfg(target_family = "unix")]
+impl Drop for Input {
+    fn drop(&mut self) {
+        crate::term::set_termios(self.fd, &self.orig_term);
+        if self.owned {
+            unsafe { libc::close(self.fd); }
+        }
+    }
+}
+
+/// Parses the key at the start of `data`, returning it (if known) and how
+/// many bytes it took up.
+fn parse_key(data: &[u8]) -> (Option<Key>, usize) {
+    match data {
+        [] => (None, 0),
+        [b'\x1B', b'[' | b'O', rest @ ..] => {
+            // CSI or SS3: parameters, then a final byte.
+            let Some(end) = rest.iter().position(|&byte| (0x40..=0x7E).contains(&byte)) else {
+                return (None, data.len());
+            };
+            let key = match &rest[..=end] {
+                b"A" => Some(Key::Up),
+                b"B" => Some(Key::Down),
+                b"C" => Some(Key::Right),
+                b"D" => Some(Key::Left),
+                b"H" | b"1~" | b"7~" => Some(Key::Home),
+                b"F" | b"4~" | b"8~" => Some(Key::End),
+                b"5~" => Some(Key::PageUp),
+                b"6~" => Some(Key::PageDown),
+                _ => None,
+            };
+            (key, end + 3)
+        }
+        [b'\x1B', ..] => (Some(Key::Escape), 1),
+        [b'\r' | b'\n', ..] => (Some(Key::Enter), 1),
+        [0x7F | 0x08, ..] => (Some(Key::Backspace), 1),
+        _ => {
+            let len = match data[0] {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            }.min(data.len());
+            let key = std::str::from_utf8(&data[..len]).ok()
+                .and_then(|text| text.chars().next())
+                .map(Key::Char);
+            (key, len)
+        }
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+pub struct Input;
+
+#[cfg(not(target_family = "unix"))]
+impl Input {
+    #[inline]
+    pub fn new(_fd: libc::c_int) -> Option<Self> {
+        None
+    }
+
+    #[inline]
+    pub fn tty() -> Option<Self> {
+        None
+    }
+
+    #[inline]
+    pub fn wait_key(&mut self, timeout: Duration) -> Result<Option<Key>, WaitError> {
+        std::thread::sleep(timeout);
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// All keys in `data`, parsed like `Input` does.
+    fn keys(mut data: &[u8]) -> Vec<Key> {
+        let mut keys = Vec::new();
+        while !data.is_empty() {
+            let (key, len) = parse_key(data);
+            assert!(len > 0 && len <= data.len(), "{len} bytes of {data:?}");
+            keys.extend(key);
+            data = &data[len..];
+        }
+        keys
+    }
+
+    #[test]
+    fn parses_single_keys() {
+        let cases: &[(&[u8], Option<Key>, usize)] = &[
+            (b"",           None,                0),
+            (b"q",          Some(Key::Char('q')), 1),
+            (b" ",          Some(Key::Char(' ')), 1),
+            ("ä".as_bytes(), Some(Key::Char('ä')), 2),
+            (b"\r",         Some(Key::Enter),    1),
+            (b"\n",         Some(Key::Enter),    1),
+            (b"\x7F",       Some(Key::Backspace), 1),
+            (b"\x08",       Some(Key::Backspace), 1),
+            (b"\x1B",       Some(Key::Escape),   1),
+            (b"\x1B[A",     Some(Key::Up),       3),
+            (b"\x1B[B",     Some(Key::Down),     3),
+            (b"\x1B[C",     Some(Key::Right),    3),
+            (b"\x1B[D",     Some(Key::Left),     3),
+            (b"\x1BOA",     Some(Key::Up),       3),
+            (b"\x1BOD",     Some(Key::Left),     3),
+            (b"\x1B[H",     Some(Key::Home),     3),
+            (b"\x1B[1~",    Some(Key::Home),     4),
+            (b"\x1B[7~",    Some(Key::Home),     4),
+            (b"\x1B[F",     Some(Key::End),      3),
+            (b"\x1B[4~",    Some(Key::End),      4),
+            (b"\x1B[8~",    Some(Key::End),      4),
+            (b"\x1B[5~",    Some(Key::PageUp),   4),
+            (b"\x1B[6~",    Some(Key::PageDown), 4),
+            // Unknown sequences are skipped as a whole.
+            (b"\x1B[1;5A",  None,                6),
+            (b"\x1B[15~",   None,                5),
+            // Truncated sequences are dropped instead of being reported.
+            (b"\x1B[",      None,                2),
+            (b"\x1B[5",     None,                3),
+            (b"\x1BO",      None,                2),
+            (b"\xC3",       None,                1),
+            (b"\xFF",       None,                1),
+        ];
+
+        for &(data, key, len) in cases {
+            assert_eq!(parse_key(data), (key, len), "{data:?}");
+        }
+    }
+
+    #[test]
+    fn parses_key_sequences() {
+        let cases: &[(&[u8], &[Key])] = &[
+            (b"\x1B[A\x1B[5~q", &[Key::Up, Key::PageUp, Key::Char('q')]),
+            (b"\x1B[6~\x1B[6~", &[Key::PageDown, Key::PageDown]),
+            (b"\x1B\x1B[B", &[Key::Escape, Key::Down]),
+            (b"\x1B[1;5Cn", &[Key::Char('n')]),
+            (b"p\x1B[", &[Key::Char('p')]),
+            (b"\x1B[5", &[]),
+        ];
+
+        for &(data, expected) in cases {
+            assert_eq!(keys(data), expected, "{data:?}");
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::fmt::{Display, Write};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use image::codecs::png::PngEncoder;
+use image::RgbaImage;
+
+use crate::canvas_size::CanvasSize;
+use crate::color::Color;
+use crate::size::Size;
+use crate::style::Style;
+
+/// Width or height argument of an inline image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    Auto,
+    Cells(u32),
+    Pixels(u32),
+}
+
+impl Display for Dimension {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => "auto".fmt(f),
+            Self::Cells(cells) => cells.fmt(f),
+            Self::Pixels(pixels) => write!(f, "{pixels}px"),
+        }
+    }
+}
+
+/// How the terminal should size an inline image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InlineSize {
+    pub width: Dimension,
+    pub height: Dimension,
+    pub preserve_aspect_ratio: bool,
+}
+
+impl InlineSize {
+    pub const AUTO: InlineSize = InlineSize { width: Dimension::Auto, height: Dimension::Auto, preserve_aspect_ratio: true };
+}
+
+/// The size arguments to use when sending the original file instead of
+/// the painted canvas, or `None` if the terminal can't reproduce what
+/// `style` and `canvas_size` would paint. That is the case when the image
+/// is moved, put on a background color or scaled in a way that can't be
+/// expressed with `width`, `height` and `preserveAspectRatio`.
+pub fn passthrough_size(style: Style, canvas_size: CanvasSize, background_color: Color, image_width: u32, image_height: u32) -> Option<InlineSize> {
+    if background_color != Color::Transparent {
+        return None;
+    }
+
+    match style {
+        Style::Position(x, y, size) => {
+            let at_origin = |value: Option<i32>| match value {
+                Some(value) => value == 0,
+                None => canvas_size.is_image(),
+            };
+            if !at_origin(x) || !at_origin(y) {
+                return None;
+            }
+            if size == Size::Scale(1) {
+                return Some(InlineSize::AUTO);
+            }
+            let (width, height) = size.to_size(image_width, image_height, 1.0);
+            Some(InlineSize {
+                width: Dimension::Pixels(width),
+                height: Dimension::Pixels(height),
+                preserve_aspect_ratio: false,
+            })
+        }
+        _ if canvas_size.is_image() => Some(InlineSize::AUTO),
+        _ => None,
+    }
+}
+
+/// Writes an OSC 1337 `File=` sequence that shows `data`, which can be in
+/// any format the terminal understands, at the cursor position. The cursor
+/// isn't moved, so that animation frames can be painted over each other.
+pub fn write_inline_image(data: &[u8], size: &InlineSize, lines: &mut String) {
+    let InlineSize { width, height, preserve_aspect_ratio } = *size;
+    let _ = write!(lines,
+        "\x1B]1337;File=inline=1;size={};width={width};height={height};preserveAspectRatio={};doNotMoveCursor=1:",
+        data.len(), preserve_aspect_ratio as u8);
+    BASE64.encode_string(data, lines);
+    lines.push('\x07');
+}
+
+/// Sends `image` as PNG with the iTerm2 inline image protocol, unless it
+/// is the same as `prev_frame`. The image is sized in cells if it covers
+/// whole cells, so that it lines up with the text grid.
+pub fn image_to_iterm2_into(prev_frame: &RgbaImage, image: &RgbaImage, (cell_width, cell_height): (u32, u32), lines: &mut String) {
+    lines.clear();
+
+    if prev_frame == image {
+        return;
+    }
+
+    let mut png = Vec::new();
+    if image.write_with_encoder(PngEncoder::new(&mut png)).is_err() {
+        return;
+    }
+
+    let (width, height) = image.dimensions();
+    let whole_cells = cell_width > 0 && cell_height > 0 && width % cell_width == 0 && height % cell_height == 0;
+    let (width, height) = if whole_cells {
+        (Dimension::Cells(width / cell_width), Dimension::Cells(height / cell_height))
+    } else {
+        (Dimension::Pixels(width), Dimension::Pixels(height))
+    };
+
+    let size = InlineSize { width, height, preserve_aspect_ratio: false };
+    write_inline_image(&png, &size, lines);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    /// Arguments and decoded payload of the OSC 1337 sequence in `lines`.
+    fn inline_image(lines: &str) -> (&str, Vec<u8>) {
+        let sequence = lines.strip_prefix("\x1B]1337;File=").unwrap().strip_suffix('\x07').unwrap();
+        let (arguments, payload) = sequence.split_once(':').unwrap();
+        (arguments, BASE64.decode(payload).unwrap())
+    }
+
+    #[test]
+    fn inline_image_arguments() {
+        let mut lines = String::new();
+        write_inline_image(b"GIF89a", &InlineSize::AUTO, &mut lines);
+        assert_eq!(lines, "\x1B]1337;File=inline=1;size=6;width=auto;height=auto;preserveAspectRatio=1;doNotMoveCursor=1:R0lGODlh\x07");
+
+        lines.clear();
+        let size = InlineSize { width: Dimension::Cells(10), height: Dimension::Pixels(48), preserve_aspect_ratio: false };
+        write_inline_image(b"", &size, &mut lines);
+        assert_eq!(inline_image(&lines), ("inline=1;size=0;width=10;height=48px;preserveAspectRatio=0;doNotMoveCursor=1", vec![]));
+    }
+
+    #[test]
+    fn png_payload() {
+        let image = RgbaImage::from_fn(16, 32, |x, y| Rgba([x as u8 * 16, y as u8 * 8, 0, 255]));
+        let mut lines = String::new();
+        image_to_iterm2_into(&RgbaImage::new(16, 32), &image, (8, 16), &mut lines);
+
+        let (arguments, png) = inline_image(&lines);
+        assert_eq!(arguments, format!("inline=1;size={};width=2;height=2;preserveAspectRatio=0;doNotMoveCursor=1", png.len()));
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), image);
+
+        image_to_iterm2_into(&image, &image, (8, 16), &mut lines);
+        assert_eq!(lines, "");
+    }
+
+    #[test]
+    fn partial_cells_are_sized_in_pixels() {
+        let image = RgbaImage::new(10, 32);
+        let mut lines = String::new();
+        image_to_iterm2_into(&RgbaImage::new(0, 0), &image, (8, 16), &mut lines);
+        assert!(inline_image(&lines).0.contains(";width=10px;height=32px;"));
+
+        image_to_iterm2_into(&RgbaImage::new(0, 0), &image, (0, 0), &mut lines);
+        assert!(inline_image(&lines).0.contains(";width=10px;height=32px;"));
+    }
+
+    #[test]
+    fn passthrough() {
+        let transparent = Color::Transparent;
+        assert_eq!(passthrough_size(Style::ShrinkToFit, CanvasSize::Image, transparent, 10, 10), Some(InlineSize::AUTO));
+        assert_eq!(passthrough_size(Style::ShrinkToFit, CanvasSize::Window, transparent, 10, 10), None);
+        assert_eq!(passthrough_size(Style::ShrinkToFit, CanvasSize::Image, Color::Solid(image::Rgb([0, 0, 0])), 10, 10), None);
+        assert_eq!(passthrough_size(Style::Position(Some(0), Some(0), Size::Scale(1)), CanvasSize::Window, transparent, 10, 10), Some(InlineSize::AUTO));
+        assert_eq!(passthrough_size(Style::Position(Some(0), Some(0), Size::Scale(2)), CanvasSize::Window, transparent, 10, 10), Some(InlineSize {
+            width: Dimension::Pixels(20),
+            height: Dimension::Pixels(20),
+            preserve_aspect_ratio: false,
+        }));
+        assert_eq!(passthrough_size(Style::Position(Some(1), Some(0), Size::Scale(1)), CanvasSize::Window, transparent, 10, 10), None);
+    }
+}
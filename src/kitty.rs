@@ -0,0 +1,309 @@
+use std::fmt::Write;
+use std::io::Write as _;
+use std::time::Duration;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::RgbaImage;
+
+/// Maximum size of the base64 payload of one escape sequence.
+const CHUNK_SIZE: usize = 4096;
+
+/// Placement ID used for all images, so that re-sending an image replaces
+/// it in place.
+const PLACEMENT_ID: u32 = 1;
+
+/// An image shown with the kitty graphics protocol. Frames added after the
+/// first one are uploaded as animation frames and played by the terminal.
+#[derive(Debug, Clone)]
+pub struct KittyImage {
+    id: u32,
+    compress: bool,
+    frames: u32,
+    prev_frame: Option<RgbaImage>,
+}
+
+impl KittyImage {
+    pub fn new(id: u32, compress: bool) -> Self {
+        Self { id, compress, frames: 0, prev_frame: None }
+    }
+
+    /// An image ID that is unlikely to collide with other programs that
+    /// show images in the same terminal.
+    #[inline]
+    pub fn default_id() -> u32 {
+        std::process::id().max(1)
+    }
+
+    /// Transmits `image` and places it at the cursor position, replacing
+    /// any previous image with the same ID. The cursor isn't moved.
+    pub fn transmit(&mut self, image: &RgbaImage, lines: &mut String) {
+        lines.clear();
+        let (width, height) = image.dimensions();
+        let header = format!("a=T,q=2,C=1,f=32,s={width},v={height},i={},p={PLACEMENT_ID}", self.id);
+        self.write_payload(&header, image.as_raw(), lines);
+        self.frames = 1;
+        self.prev_frame = Some(image.clone());
+    }
+
+    /// Appends `image` as an animation frame that is shown for `gap`.
+    ///
+    /// The first frame is transmitted and placed like with
+    /// [`transmit`](Self::transmit). Later frames only send the rectangle
+    /// that differs from the previous frame, which the terminal composes
+    /// onto a copy of that frame.
+    pub fn add_frame(&mut self, image: &RgbaImage, gap: Duration, lines: &mut String) {
+        let gap = gap.as_millis().clamp(1, u32::MAX as u128);
+
+        let Some(prev_frame) = &self.prev_frame else {
+            self.transmit(image, lines);
+            let _ = write!(lines, "\x1B_Ga=a,q=2,i={},r=1,z={gap}\x1B\\", self.id);
+            return;
+        };
+
+        lines.clear();
+        let base = self.frames;
+        let (x, y, width, height) = changed_rect(prev_frame, image).unwrap_or((0, 0, 1, 1));
+        let data = rect_data(image, x, y, width, height);
+
+        // c: frame the new one is based on, X=1: replace instead of blending.
+        let header = format!("a=f,q=2,f=32,i={},x={x},y={y},s={width},v={height},c={base},X=1,z={gap}", self.id);
+        self.write_payload(&header, &data, lines);
+        self.frames += 1;
+        self.prev_frame = Some(image.clone());
+    }
+
+    /// Shows `image` instead of the current one at the same place.
+    ///
+    /// The first image is transmitted and placed like with
+    /// [`transmit`](Self::transmit). After that only the rectangle that
+    /// differs from the previous image is sent, as an edit of the first
+    /// frame, so the terminal keeps a single image no matter how many
+    /// images are shown. Nothing is written if the image didn't change.
+    pub fn update(&mut self, image: &RgbaImage, lines: &mut String) {
+        let prev_frame = match &self.prev_frame {
+            Some(prev_frame) if self.frames == 1 && prev_frame.dimensions() == image.dimensions() => prev_frame,
+            _ => {
+                self.transmit(image, lines);
+                return;
+            }
+        };
+
+        lines.clear();
+        let Some((x, y, width, height)) = changed_rect(prev_frame, image) else {
+            return;
+        };
+        let data = rect_data(image, x, y, width, height);
+
+        // r=1: edit the first frame instead of adding one, X=1: replace
+        // instead of blending, so pixels can become transparent.
+        let header = format!("a=f,q=2,f=32,i={},r=1,x={x},y={y},s={width},v={height},X=1", self.id);
+        self.write_payload(&header, &data, lines);
+        self.prev_frame = Some(image.clone());
+    }
+
+    /// Starts the animation. `loop_count` of `None` loops forever.
+    pub fn play(&self, loop_count: Option<u32>, lines: &mut String) {
+        // v=1 means infinite, v=n loops n-1 times.
+        let loops = loop_count.map_or(1, |count| count.saturating_add(1));
+        let _ = write!(lines, "\x1B_Ga=a,q=2,i={},s=3,v={loops}\x1B\\", self.id);
+    }
+
+    /// Stops the animation, leaving the current frame on screen.
+    pub fn stop(&self, lines: &mut String) {
+        let _ = write!(lines, "\x1B_Ga=a,q=2,i={},s=1\x1B\\", self.id);
+    }
+
+    /// Deletes the image and all its frames from the terminal.
+    pub fn delete(&self, lines: &mut String) {
+        let _ = write!(lines, "\x1B_Ga=d,q=2,d=I,i={}\x1B\\", self.id);
+    }
+
+    fn write_payload(&self, header: &str, data: &[u8], lines: &mut String) {
+        let encoded = if self.compress {
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+            let _ = encoder.write_all(data);
+            BASE64.encode(encoder.finish().unwrap_or_default())
+        } else {
+            BASE64.encode(data)
+        };
+
+        let compression = if self.compress { ",o=z" } else { "" };
+        let mut chunks = encoded.as_bytes().chunks(CHUNK_SIZE).peekable();
+        let mut first = true;
+        loop {
+            let chunk = chunks.next().unwrap_or_default();
+            let more = chunks.peek().is_some() as u8;
+            let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+            if first {
+                let _ = write!(lines, "\x1B_G{header}{compression},m={more};{chunk}\x1B\\");
+                first = false;
+            } else {
+                let _ = write!(lines, "\x1B_Gm={more};{chunk}\x1B\\");
+            }
+            if more == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// RGBA data of the rectangle of `image` at `x`, `y`, row by row.
+fn rect_data(image: &RgbaImage, x: u32, y: u32, width: u32, height: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(width as usize * height as usize * 4);
+    for row in y..y + height {
+        let start = (row as usize * image.width() as usize + x as usize) * 4;
+        data.extend_from_slice(&image.as_raw()[start..start + width as usize * 4]);
+    }
+    data
+}
+
+/// Bounding box `(x, y, width, height)` of the pixels that differ between
+/// the two images, or `None` if they are equal.
+fn changed_rect(prev_frame: &RgbaImage, image: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let mut min_x = u32::MAX;
+    let mut min_y = u32::MAX;
+    let mut max_x = 0;
+    let mut max_y = 0;
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if prev_frame.get_pixel(x, y) != pixel {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    if min_x > max_x {
+        None
+    } else {
+        Some((min_x, min_y, max_x - min_x + 1, max_y - min_y + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    use flate2::read::ZlibDecoder;
+    use image::Rgba;
+
+    use crate::encoder::{EncodeOptions, Encoder, EncoderState};
+
+    /// Control data and payload of each APC sequence in `lines`.
+    fn commands(lines: &str) -> Vec<(&str, &str)> {
+        lines.split_terminator("\x1B\\").map(|command| {
+            let command = command.strip_prefix("\x1B_G").unwrap();
+            command.split_once(';').unwrap_or((command, ""))
+        }).collect()
+    }
+
+    fn two_pixels() -> RgbaImage {
+        RgbaImage::from_pixel(2, 1, Rgba([1, 2, 3, 4]))
+    }
+
+    #[test]
+    fn transmit() {
+        let mut kitty = KittyImage::new(7, false);
+        let mut lines = String::new();
+        kitty.transmit(&two_pixels(), &mut lines);
+        assert_eq!(commands(&lines), [("a=T,q=2,C=1,f=32,s=2,v=1,i=7,p=1,m=0", "AQIDBAECAwQ=")]);
+    }
+
+    #[test]
+    fn payload_chunks() {
+        let image = RgbaImage::from_fn(64, 64, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let mut lines = String::new();
+        KittyImage::new(7, false).transmit(&image, &mut lines);
+
+        let commands = commands(&lines);
+        assert_eq!(commands.len(), 6);
+        assert_eq!(commands[0].0, "a=T,q=2,C=1,f=32,s=64,v=64,i=7,p=1,m=1");
+        assert!(commands[1..5].iter().all(|&(control, _)| control == "m=1"));
+        assert_eq!(commands[5].0, "m=0");
+        assert!(commands.iter().all(|&(_, payload)| payload.len() <= CHUNK_SIZE));
+
+        let payload: String = commands.iter().map(|&(_, payload)| payload).collect();
+        assert_eq!(BASE64.decode(payload).unwrap(), image.as_raw().as_slice());
+    }
+
+    #[test]
+    fn compressed_payload() {
+        let mut lines = String::new();
+        KittyImage::new(7, true).transmit(&two_pixels(), &mut lines);
+
+        let commands = commands(&lines);
+        assert_eq!(commands[0].0, "a=T,q=2,C=1,f=32,s=2,v=1,i=7,p=1,o=z,m=0");
+        let mut data = Vec::new();
+        ZlibDecoder::new(BASE64.decode(commands[0].1).unwrap().as_slice()).read_to_end(&mut data).unwrap();
+        assert_eq!(data, [1, 2, 3, 4, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn animation_frames() {
+        let mut kitty = KittyImage::new(7, false);
+        let mut lines = String::new();
+        let mut image = two_pixels();
+        kitty.add_frame(&image, Duration::from_millis(40), &mut lines);
+        assert_eq!(commands(&lines), [
+            ("a=T,q=2,C=1,f=32,s=2,v=1,i=7,p=1,m=0", "AQIDBAECAwQ="),
+            ("a=a,q=2,i=7,r=1,z=40", ""),
+        ]);
+
+        image.put_pixel(1, 0, Rgba([0, 0, 0, 0]));
+        kitty.add_frame(&image, Duration::from_millis(100), &mut lines);
+        assert_eq!(commands(&lines), [("a=f,q=2,f=32,i=7,x=1,y=0,s=1,v=1,c=1,X=1,z=100,m=0", "AAAAAA==")]);
+
+        lines.clear();
+        kitty.play(None, &mut lines);
+        kitty.play(Some(2), &mut lines);
+        kitty.stop(&mut lines);
+        kitty.delete(&mut lines);
+        assert_eq!(commands(&lines), [
+            ("a=a,q=2,i=7,s=3,v=1", ""),
+            ("a=a,q=2,i=7,s=3,v=3", ""),
+            ("a=a,q=2,i=7,s=1", ""),
+            ("a=d,q=2,d=I,i=7", ""),
+        ]);
+    }
+
+    #[test]
+    fn updates_edit_the_first_frame() {
+        let mut kitty = KittyImage::new(7, false);
+        let mut lines = String::new();
+        let mut image = two_pixels();
+        kitty.update(&image, &mut lines);
+        assert_eq!(commands(&lines)[0].0, "a=T,q=2,C=1,f=32,s=2,v=1,i=7,p=1,m=0");
+
+        kitty.update(&image, &mut lines);
+        assert_eq!(lines, "");
+
+        image.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        kitty.update(&image, &mut lines);
+        assert_eq!(commands(&lines), [("a=f,q=2,f=32,i=7,r=1,x=0,y=0,s=1,v=1,X=1,m=0", "AAAAAA==")]);
+
+        // A new size needs a new image, under the same ID.
+        kitty.update(&RgbaImage::new(1, 1), &mut lines);
+        assert_eq!(commands(&lines), [("a=T,q=2,C=1,f=32,s=1,v=1,i=7,p=1,m=0", "AAAAAA==")]);
+    }
+
+    #[test]
+    fn encoder_reuses_the_image() {
+        let options = EncodeOptions::default();
+        let mut state = EncoderState::default();
+        let mut lines = String::new();
+        let blank = RgbaImage::new(2, 1);
+        let first = two_pixels();
+        let mut second = first.clone();
+        second.put_pixel(1, 0, Rgba([9, 9, 9, 255]));
+
+        Encoder::Kitty.encode_into(&blank, &first, false, &options, &mut state, &mut lines);
+        assert!(lines.starts_with("\x1B_Ga=T,"), "{lines:?}");
+
+        Encoder::Kitty.encode_into(&first, &second, false, &options, &mut state, &mut lines);
+        let id = KittyImage::default_id();
+        assert!(lines.starts_with(&format!("\x1B_Ga=f,q=2,f=32,i={id},r=1,x=1,y=0,s=1,v=1,X=1,m=0;")), "{lines:?}");
+    }
+}
@@ -0,0 +1,28 @@
+pub mod image_to_ansi;
+pub mod ascii;
+pub mod blocks;
+pub mod braille;
+pub mod cells;
+pub mod canvas_size;
+pub mod color;
+pub mod color_depth;
+pub mod compositor;
+pub mod decode;
+pub mod dither;
+pub mod encoder;
+pub mod html;
+pub mod input;
+pub mod iterm2;
+pub mod kitty;
+pub mod palette;
+pub mod playback;
+pub mod ramp;
+pub mod renderer;
+pub mod sixel;
+pub mod size;
+pub mod style;
+pub mod term;
+pub mod terminfo;
+
+#[cfg(feature = "ratatui")]
+pub mod widget;
@@ -1,24 +1,37 @@
-use std::mem::MaybeUninit;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use cli::args::Args;
-use cli::canvas_size::CanvasSize;
-use cli::color::Color;
-use cli::style::Style;
-use clap::Parser;
-use image::codecs::gif::GifDecoder;
-use image::codecs::png::PngDecoder;
-use image::codecs::webp::WebPDecoder;
+use ansi_img::canvas_size::CanvasSize;
+use ansi_img::color::Color;
+use ansi_img::color_depth::ColorDepth;
+use ansi_img::dither::Dither;
+use ansi_img::encoder::Encoder;
+use ansi_img::style::Style;
+use ansi_img::decode::{decode_streaming, StreamedImage};
+use ansi_img::html;
+use ansi_img::image_to_ansi::cursor_to_last_row;
+use ansi_img::input::{Input, Key, WaitError};
+use ansi_img::iterm2;
+use ansi_img::kitty::KittyImage;
+use ansi_img::playback::Playback;
+use ansi_img::renderer::{compose_frame, fill_color, Renderer};
+use ansi_img::term::{self, TerminalGuard};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
 use image::io::Reader as ImageReader;
 use image::error::ImageResult;
-use image::{AnimationDecoder, DynamicImage, Frame, GenericImage, ImageDecoder, RgbaImage, Pixel};
-use image::imageops;
-use image_to_ansi::image_to_ansi_into;
+use image::{GenericImage, RgbaImage};
 
-pub mod image_to_ansi;
-pub mod cli;
+mod cli;
+
+use crate::cli::args::Args;
+use crate::cli::cell_aspect::CellAspect;
+use crate::cli::format::Format;
 
 fn interruptable_sleep(duration: Duration) -> bool {
     #[cfg(target_family = "unix")]
@@ -28,7 +41,7 @@ fn interruptable_sleep(duration: Duration) -> bool {
             tv_nsec: duration.subsec_nanos() as i64,
         };
         let ret = unsafe { libc::nanosleep(&req, std::ptr::null_mut()) };
-        return ret == 0;
+        ret == 0
     }
 
     #[cfg(not(target_family = "unix"))]
@@ -38,326 +51,1110 @@ fn interruptable_sleep(duration: Duration) -> bool {
     }
 }
 
-#[inline]
-fn fill_color(image: &mut RgbaImage, color: Color) {
-    match color {
-        Color::Transparent => {
-            image.fill(0);
-        }
-        Color::Solid(rgb) => {
-            let rgba = rgb.to_rgba();
-            for pixel in image.pixels_mut() {
-                *pixel = rgba;
+/// Expands arguments that don't name an existing file as glob patterns.
+/// Patterns without matches are kept, so that opening them reports the
+/// error.
+fn expand_paths(paths: Vec<OsString>) -> Vec<PathBuf> {
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = PathBuf::from(path);
+        if !path.exists() {
+            if let Some(pattern) = path.to_str() {
+                if let Ok(matches) = glob::glob(pattern) {
+                    let len = expanded.len();
+                    expanded.extend(matches.flatten().filter(|path| path.is_file()));
+                    if expanded.len() > len {
+                        continue;
+                    }
+                }
             }
         }
+        expanded.push(path);
     }
+    expanded
 }
 
-fn main() -> ImageResult<()> {
-    use std::io::Write;
+/// What to do after showing an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    Next,
+    Prev,
+    Quit,
+}
 
-    let args = Args::parse();
+impl Action {
+    #[inline]
+    fn from_key(key: Key) -> Option<Self> {
+        match key {
+            Key::Char('n') | Key::PageDown | Key::Enter => Some(Action::Next),
+            Key::Char('p') | Key::PageUp | Key::Backspace => Some(Action::Prev),
+            Key::Char('q') | Key::Escape => Some(Action::Quit),
+            _ => None,
+        }
+    }
+}
 
-    let alpha_threshold = args.alpha_threshold;
-    let style = args.style;
-    let canvas_size = args.canvas_size;
-    let run_anim = Arc::new(AtomicBool::new(true));
-    let path = args.path;
-    let filter = args.filter.into();
-    let background_color = args.background_color;
-    let endl = args.line_end.as_str();
-    let inline = args.inline;
+/// Keys for controlling animations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Control {
+    Action(Action),
+    Pause,
+    StepForward,
+    StepBack,
+    Faster,
+    Slower,
+    Restart,
+    /// A key without a binding.
+    Other,
+}
 
-    {
-        let run_anim = run_anim.clone();
-        let _ = ctrlc::set_handler(move || {
-            run_anim.store(false, Ordering::Relaxed);
-        });
+impl Control {
+    #[inline]
+    fn from_key(key: Key) -> Option<Self> {
+        match key {
+            Key::Char(' ') => Some(Control::Pause),
+            Key::Right => Some(Control::StepForward),
+            Key::Left => Some(Control::StepBack),
+            Key::Char('+' | '=') => Some(Control::Faster),
+            Key::Char('-') => Some(Control::Slower),
+            Key::Char('r') => Some(Control::Restart),
+            key => Some(Action::from_key(key).map_or(Control::Other, Control::Action)),
+        }
     }
+}
 
-    let reader = ImageReader::open(path)?.with_guessed_format()?;
+/// Largest factor `+` and `-` change the speed of animations by.
+const MAX_SPEED: f64 = 16.0;
 
-    let mut lock = std::io::stdout().lock();
-    // CSI ?  7 l     No Auto-Wrap Mode (DECAWM), VT100.
-    // CSI ? 25 l     Hide cursor (DECTCEM), VT220
-    print!("\x1B[?25l\x1B[?7l");
+/// How far an animation may fall behind before it stops dropping frames to
+/// catch up and continues from where it is instead.
+const MAX_LAG: Duration = Duration::from_secs(1);
 
-    let mut linebuf = String::new();
+/// Layout of `--grid` in character cells.
+#[derive(Debug, Clone, Copy)]
+struct Grid {
+    columns: u32,
+    rows: u32,
+    thumbnail_width: u32,
+    thumbnail_height: u32,
+    caption_height: u32,
+    gutter: u32,
+}
+
+impl Grid {
+    #[inline]
+    fn per_page(&self) -> usize {
+        (self.columns * self.rows) as usize
+    }
 
-    enum DecodedImage {
-        Animated(u32, u32, Vec<Frame>),
-        Still(RgbaImage)
+    /// Column and row of the top left cell of thumbnail `index`.
+    #[inline]
+    fn position(&self, index: usize) -> (u32, u32) {
+        let index = index as u32;
+        let x = (index % self.columns) * (self.thumbnail_width + self.gutter);
+        let y = (index / self.columns) * (self.thumbnail_height + self.caption_height + self.gutter);
+        (x, y)
     }
+}
+
+/// `-` stands for stdin.
+#[inline]
+fn is_stdin(path: &Path) -> bool {
+    path.as_os_str() == "-"
+}
 
-    impl DecodedImage {
-        #[inline]
-        fn size(&self) -> (u32, u32) {
-            match self {
-                DecodedImage::Animated(width, height, _) => (*width, *height),
-                DecodedImage::Still(img) => (img.width(), img.height()),
+struct Viewer {
+    args: Args,
+    color_depth: ColorDepth,
+    cell_aspect: f64,
+    run_anim: Arc<AtomicBool>,
+    input: Option<Input>,
+    slideshow: bool,
+    first: bool,
+    linebuf: String,
+    errors: Vec<String>,
+    stdin: Option<Arc<[u8]>>,
+    /// Whether SIGWINCH tells about window resizes.
+    watch_resize: bool,
+}
+
+impl Viewer {
+    /// Sleeps for `duration`, returning early if a key for an action is
+    /// pressed or on Ctrl+C.
+    fn wait(&mut self, duration: Duration) -> Option<Action> {
+        let deadline = Instant::now() + duration;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            if let Some(Control::Action(action)) = self.wait_control(deadline - now) {
+                return Some(action);
             }
         }
     }
 
-    let anim = match reader.format() {
-        Some(image::ImageFormat::Gif) => {
-            let decoder = GifDecoder::new(reader.into_inner())?;
-            let (width, height) = decoder.dimensions();
-            let frames = decoder.into_frames().collect_frames()?;
-
-            if frames.len() == 1 {
-                DecodedImage::Still(frames.into_iter().next().unwrap().into_buffer())
-            } else {
-                DecodedImage::Animated(width, height, frames)
+    /// Sleeps for `duration`, returning early if a key is pressed or on
+    /// Ctrl+C. Returns `None` if no key was pressed, which can also happen
+    /// early.
+    fn wait_control(&mut self, duration: Duration) -> Option<Control> {
+        let Some(input) = &mut self.input else {
+            if !interruptable_sleep(duration) && !term::resized() {
+                self.run_anim.store(false, Ordering::Relaxed);
+                return Some(Control::Action(Action::Quit));
             }
-        },
-        Some(image::ImageFormat::WebP) => {
-            let decoder = WebPDecoder::new(reader.into_inner())?;
-            if decoder.has_animation() {
-                let (width, height) = decoder.dimensions();
-                let frames = decoder.into_frames().collect_frames()?;
-
-                if frames.len() == 1 {
-                    DecodedImage::Still(frames.into_iter().next().unwrap().into_buffer())
+            return None;
+        };
+
+        match input.wait_key(duration) {
+            Ok(Some(key)) => Control::from_key(key),
+            Ok(None) => None,
+            Err(WaitError::Interrupted) => {
+                if self.run_anim.load(Ordering::Relaxed) {
+                    None
                 } else {
-                    DecodedImage::Animated(width, height, frames)
+                    Some(Control::Action(Action::Quit))
                 }
-            } else {
-                DecodedImage::Still(DynamicImage::from_decoder(decoder)?.to_rgba8())
             }
-        },
-        Some(image::ImageFormat::Png) => {
-            let decoder = PngDecoder::new(reader.into_inner())?;
-            if decoder.is_apng()? {
-                let (width, height) = decoder.dimensions();
-                let frames = decoder.apng()?.into_frames().collect_frames()?;
-
-                if frames.len() == 1 {
-                    DecodedImage::Still(frames.into_iter().next().unwrap().into_buffer())
-                } else {
-                    DecodedImage::Animated(width, height, frames)
+            Err(WaitError::Closed) => {
+                // Nothing to read anymore, so just sleep.
+                self.input = None;
+                self.wait_control(duration)
+            }
+        }
+    }
+
+    /// Like [`wait`](Self::wait), but `None` waits until a key is pressed.
+    fn wait_for(&mut self, duration: Option<Duration>) -> Option<Action> {
+        let mut remaining = duration;
+        while self.run_anim.load(Ordering::Relaxed) && remaining.is_none_or(|remaining| !remaining.is_zero()) {
+            let duration = remaining.unwrap_or(Duration::from_secs(1)).min(Duration::from_secs(1));
+            if let Some(action) = self.wait(duration) {
+                return Some(action);
+            }
+            if let Some(remaining) = &mut remaining {
+                *remaining -= duration;
+            }
+        }
+        None
+    }
+
+    /// Waits until `deadline`, or forever if it is `None`, returning early
+    /// if any key is pressed or on Ctrl+C. Returns `None` if the window was
+    /// resized.
+    fn wait_or_resize(&mut self, deadline: Option<Instant>) -> Option<Action> {
+        loop {
+            if term::take_resized() {
+                return None;
+            }
+            if !self.run_anim.load(Ordering::Relaxed) {
+                return Some(Action::Next);
+            }
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Some(Action::Next);
+                    }
+                    (deadline - now).min(Duration::from_secs(1))
                 }
+                None => Duration::from_secs(1),
+            };
+            match self.wait_control(timeout) {
+                Some(Control::Action(action)) => return Some(action),
+                // Any other key goes on like `n`.
+                Some(_) => return Some(Action::Next),
+                None => {}
+            }
+        }
+    }
+
+    #[inline]
+    fn duration(&self) -> Duration {
+        Duration::try_from_secs_f64(self.args.duration).unwrap_or(Duration::ZERO)
+    }
+
+    /// Delay to actually use for a frame stored with `delay`, before
+    /// applying the speed.
+    #[inline]
+    fn frame_delay(&self, delay: Duration) -> Duration {
+        if let Some(fps) = self.args.fps {
+            return Duration::from_secs_f64(1.0 / fps);
+        }
+        match self.args.min_delay {
+            Some(min_delay) if delay <= Duration::from_millis(10) => Duration::from_millis(min_delay),
+            _ => delay,
+        }
+    }
+
+    /// Shortest time between two shown frames.
+    #[inline]
+    fn min_frame_time(&self) -> Duration {
+        self.args.max_fps.map(|max_fps| Duration::from_secs_f64(1.0 / max_fps)).unwrap_or(Duration::ZERO)
+    }
+
+    /// Times to loop an animation that the file says should be played
+    /// `file_loop_count` times, `None` meaning forever. In a slideshow an
+    /// endless animation plays once before advancing, unless told
+    /// otherwise.
+    #[inline]
+    fn loop_count(&self, file_loop_count: Option<u32>) -> Option<u32> {
+        match self.args.loop_count {
+            Some(loop_count) if loop_count >= 0 => Some(loop_count.min(u32::MAX as i64) as u32),
+            Some(_) => None,
+            None if self.slideshow => Some(file_loop_count.unwrap_or(1)),
+            None => file_loop_count,
+        }
+    }
+
+    /// Whether to show an image on the alternate screen. By default that is
+    /// done when it stays on screen for a while and may be interacted with.
+    #[inline]
+    fn use_alt_screen(&self, animated: bool) -> bool {
+        self.args.alt_screen.unwrap_or_else(|| {
+            !self.args.inline &&
+            (animated || self.slideshow || self.args.watch) &&
+            std::io::stdout().is_terminal()
+        })
+    }
+
+    /// Shows the position and speed of an animation on the row below it,
+    /// which is `row` rows below its top.
+    fn print_status(&self, row: u32, index: usize, frame_count: Option<usize>, delay: Duration, speed: f64, paused: bool) {
+        if self.args.inline {
+            print!("\x1B[u");
+            if row > 0 {
+                print!("\x1B[{row}B");
+            }
+        } else {
+            print!("\x1B[{};1H", row + 1);
+        }
+
+        // CSI 2 K     Erase the whole line.
+        print!("\x1B[0m\x1B[2Kframe {}/", index + 1);
+        match frame_count {
+            Some(frame_count) => print!("{frame_count}"),
+            None => print!("?"),
+        }
+        print!("  {} ms", delay.as_millis());
+        if !delay.is_zero() {
+            print!("  {:.1} fps", speed / delay.as_secs_f64());
+        }
+        print!("  {speed}x");
+        if paused {
+            print!("  paused");
+        }
+    }
+
+    /// Reads all of stdin once, so it can be shown again.
+    fn stdin_data(&mut self) -> std::io::Result<Arc<[u8]>> {
+        if let Some(data) = &self.stdin {
+            return Ok(data.clone());
+        }
+        let mut data = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut data)?;
+        let data: Arc<[u8]> = data.into();
+        self.stdin = Some(data.clone());
+        Ok(data)
+    }
+
+    /// Decodes the file at `path` or stdin, guessing the format from its
+    /// contents. Animations are decoded while they are played.
+    fn decode(&mut self, path: &Path) -> ImageResult<StreamedImage> {
+        if is_stdin(path) {
+            let data = self.stdin_data()?;
+            decode_streaming(move || Ok(ImageReader::new(Cursor::new(data.clone())).with_guessed_format()?))
+        } else {
+            let path = path.to_path_buf();
+            decode_streaming(move || Ok(ImageReader::open(&path)?.with_guessed_format()?))
+        }
+    }
+
+    /// Decodes a still image, or the first frame of an animation.
+    fn load_still(&mut self, path: &Path) -> ImageResult<RgbaImage> {
+        match self.decode(path)? {
+            StreamedImage::Still(image) => Ok(image),
+            StreamedImage::Animated(width, height, mut frames, _) => {
+                let mut image = RgbaImage::new(width, height);
+                if let Some(frame) = frames.next() {
+                    compose_frame(&mut image, &frame?, Color::Transparent)?;
+                }
+                Ok(image)
+            }
+        }
+    }
+
+    fn renderer(&self, dither: Dither) -> Renderer {
+        Renderer::new()
+            .style(self.args.style)
+            .canvas_size(self.args.canvas_size)
+            .filter(self.args.filter.into())
+            .background_color(self.args.background_color)
+            .alpha_threshold(self.args.alpha_threshold)
+            .encoder(self.args.encoder)
+            .cell_aspect(self.cell_aspect)
+            .color_depth(self.color_depth)
+            .dither(dither)
+            .threshold(self.args.threshold)
+            .ramp(self.args.ramp.clone())
+            .compress(self.args.compress)
+    }
+
+    /// Prepares the screen for an image that is `lines` rows high.
+    fn begin(&mut self, lines: u32) {
+        if self.args.inline {
+            if !self.first {
+                // CSI J     Erase below the previous image.
+                print!("\x1B[u\x1B[J");
+            }
+            // Make sure everything is in view because when moving the cursor beyond
+            // the bottom screen edge it will stay at the last line and not scroll
+            // the screen.
+            if self.args.no_padding {
+                print!("\x1B[s");
             } else {
-                DecodedImage::Still(DynamicImage::from_decoder(decoder)?.to_rgba8())
+                for _ in 0..lines {
+                    println!();
+                }
+                print!("\x1B[{lines}A\x1B[s");
             }
-        },
-        _ => DecodedImage::Still(reader.decode()?.to_rgba8())
-    };
+        } else {
+            print!("\x1B[2J");
+        }
+        self.first = false;
+    }
 
-    let mut term_canvas = match canvas_size {
-        CanvasSize::Exact(width, height) => Some(RgbaImage::new(width, height)),
-        CanvasSize::Window =>
-            term_size::dimensions().map(|(width, height)|
-                match background_color {
-                    Color::Transparent => RgbaImage::new(width as u32, height as u32 * 2),
-                    Color::Solid(rgb) => RgbaImage::from_pixel(width as u32, height as u32 * 2, rgb.to_rgba()),
-                }),
-        CanvasSize::Image =>
-            match style {
-                Style::Position(x, y, size) => {
-                    let (image_width, image_height) = anim.size();
-                    let (w, h) = size.to_size(image_width, image_height);
-                    let x = x.unwrap_or(0);
-                    let y = y.unwrap_or(0);
-
-                    let w = (w as i64 + x as i64).max(0);
-                    let h = (h as i64 + y as i64).max(0);
-                    let w = if w > u32::MAX as i64 { u32::MAX } else { w as u32 };
-                    let h = if h > u32::MAX as i64 { u32::MAX } else { h as u32 };
-
-                    if let Color::Solid(rgb) = background_color {
-                        Some(RgbaImage::from_pixel(w, h, rgb.to_rgba()))
-                    } else {
-                        Some(RgbaImage::new(w, h))
-                    }
-                },
-                _ =>
-                    if let Color::Solid(rgb) = background_color {
-                        let (width, height) = anim.size();
-                        Some(RgbaImage::from_pixel(width, height, rgb.to_rgba()))
-                    } else {
-                        None
-                    }
-            },
-    };
+    /// The canvas for `--grid`, which covers the window unless an exact
+    /// size was given.
+    fn grid_canvas(&self, renderer: &Renderer) -> RgbaImage {
+        let (cell_width, cell_height) = self.args.encoder.cell_size();
+        let canvas = if self.args.canvas_size.is_image() {
+            renderer.clone().canvas_size(CanvasSize::Window).new_canvas(0, 0)
+        } else {
+            renderer.new_canvas(0, 0)
+        };
+        canvas.unwrap_or_else(|| {
+            let mut canvas = RgbaImage::new(80 * cell_width, 24 * cell_height);
+            fill_color(&mut canvas, self.args.background_color);
+            canvas
+        })
+    }
 
-    let mut prev_frame = if let Some(term_canvas) = &term_canvas {
-        RgbaImage::new(term_canvas.width(), term_canvas.height())
-    } else {
-        let (width, height) = anim.size();
-        RgbaImage::new(width, height)
-    };
+    /// Fits as many thumbnails of about `--thumbnail-width` on `canvas` as
+    /// possible. Thumbnails are square on screen, given canvas pixels that
+    /// are `pixel_aspect` times as wide as they are high.
+    fn grid(&self, canvas: &RgbaImage, pixel_aspect: f64) -> Grid {
+        let (cell_width, cell_height) = self.args.encoder.cell_size();
+        let columns = (canvas.width() / cell_width).max(1);
+        let rows = (canvas.height() / cell_height).max(1);
+        let gutter = self.args.gutter;
+        let caption_height = self.args.captions as u32;
 
-    #[cfg(target_family = "unix")]
-    let mut term = MaybeUninit::<libc::termios>::zeroed();
+        let thumbnail_width = self.args.thumbnail_width.clamp(1, columns);
+        let grid_columns = ((columns + gutter) / (thumbnail_width + gutter)).max(1);
+        // Spread left over space over the thumbnails.
+        let thumbnail_width = ((columns + gutter) / grid_columns).saturating_sub(gutter).max(1);
 
-    #[cfg(target_family = "unix")]
-    let mut term = if unsafe { libc::tcgetattr(libc::STDIN_FILENO, term.as_mut_ptr()) == 0 } {
-        Some(unsafe { term.assume_init_mut() })
-    } else {
-        None
-    };
+        let thumbnail_height = ((thumbnail_width * cell_width) as f64 * pixel_aspect / cell_height as f64).ceil() as u32;
+        let thumbnail_height = thumbnail_height.clamp(1, rows.saturating_sub(caption_height).max(1));
+        let grid_rows = ((rows + gutter) / (thumbnail_height + caption_height + gutter)).max(1);
 
-    #[cfg(target_family = "unix")]
-    if let Some(term) = &mut term {
-        (*term).c_lflag &= !libc::ECHO;
-        unsafe { libc::tcsetattr(libc::STDIN_FILENO, 0, *term); }
+        Grid {
+            columns: grid_columns,
+            rows: grid_rows,
+            thumbnail_width,
+            thumbnail_height,
+            caption_height,
+            gutter,
+        }
     }
 
-    if inline {
-        // Make sure everything is in view because when moving the cursor beyond
-        // the bottom screen edge it will stay at the last line and not scroll
-        // the screen.
-        if args.no_padding {
-            print!("\x1B[s");
+    /// Writes `paths` as text or HTML (see `--format`) to `output`, or to
+    /// stdout for `-`, instead of showing them.
+    fn export(&mut self, output: &Path, paths: &[PathBuf]) -> ImageResult<()> {
+        let mut out: Box<dyn Write> = if output.as_os_str() == "-" {
+            Box::new(std::io::stdout().lock())
         } else {
-            let lines = (prev_frame.height() + 1) / 2;
-            for _ in 0..lines {
-                println!();
+            Box::new(BufWriter::new(File::create(output)?))
+        };
+        let endl = self.args.line_end.as_str();
+        let renderer = self.renderer(self.args.dither.resolve(false));
+        let mut linebuf = std::mem::take(&mut self.linebuf);
+        let format = self.args.format;
+        let classes = self.args.css_classes;
+
+        let mut css = String::new();
+        if format.is_html() && classes {
+            html::palette_css_into(self.color_depth, endl, &mut css);
+        }
+        linebuf.clear();
+        match format {
+            Format::Ansi => {}
+            Format::Html => if !css.is_empty() {
+                linebuf.push_str("<style>");
+                linebuf.push_str(endl);
+                linebuf.push_str(&css);
+                linebuf.push_str("</style>");
+                linebuf.push_str(endl);
+            }
+            Format::HtmlPage => {
+                let title = paths.iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                html::page_start_into(&title, &css, endl, &mut linebuf);
             }
-            print!("\x1B[{lines}A\x1B[s");
         }
-    } else {
-        print!("\x1B[2J");
+        out.write_all(linebuf.as_bytes())?;
+
+        for path in paths {
+            let mut image = match self.load_still(path) {
+                Ok(image) => image,
+                Err(error) if paths.len() > 1 => {
+                    eprint!("{}: {error}{endl}", path.display());
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+
+            let canvas = if let Some(mut canvas) = renderer.new_canvas(image.width(), image.height()) {
+                renderer.paint(&image, &mut canvas);
+                canvas
+            } else {
+                renderer.quantize(&mut image);
+                image
+            };
+            if format.is_html() {
+                renderer.encode_html_into(&canvas, classes, endl, &mut linebuf);
+            } else {
+                renderer.encode_text_into(&canvas, endl, &mut linebuf);
+            }
+            out.write_all(linebuf.as_bytes())?;
+        }
+
+        if format == Format::HtmlPage {
+            linebuf.clear();
+            html::page_end_into(endl, &mut linebuf);
+            out.write_all(linebuf.as_bytes())?;
+        }
+        out.flush()?;
+
+        self.linebuf = linebuf;
+        Ok(())
+    }
+
+    /// Shows `paths` as one page of `grid`.
+    fn show_grid(&mut self, grid: &Grid, paths: &[PathBuf]) -> Action {
+        if self.use_alt_screen(false) {
+            term::enter_alt_screen();
+        }
+        let (cell_width, cell_height) = self.args.encoder.cell_size();
+        let mut renderer = self.renderer(self.args.dither.resolve(false));
+        let mut canvas = self.grid_canvas(&renderer);
+        let mut thumbnail = RgbaImage::new(grid.thumbnail_width * cell_width, grid.thumbnail_height * cell_height);
+
+        for (index, path) in paths.iter().enumerate() {
+            let image = match self.load_still(path) {
+                Ok(image) => image,
+                Err(error) => {
+                    self.errors.push(format!("{}: {error}", path.display()));
+                    continue;
+                }
+            };
+            fill_color(&mut thumbnail, self.args.background_color);
+            Style::Contain.paint(&image, &mut thumbnail, self.args.filter.into(), renderer.pixel_aspect());
+
+            let (x, y) = grid.position(index);
+            let _ = canvas.copy_from(&thumbnail, x * cell_width, y * cell_height);
+        }
+
+        renderer.quantize(&mut canvas);
+
+        let mut linebuf = std::mem::take(&mut self.linebuf);
+        let prev_frame = RgbaImage::new(canvas.width(), canvas.height());
+        renderer.encode_into(&prev_frame, &canvas, self.args.canvas_size.is_window(), &mut linebuf);
+
+        let mut lock = std::io::stdout().lock();
+        self.begin(canvas.height().div_ceil(cell_height));
+        if self.args.inline {
+            print!("{linebuf}");
+        } else {
+            print!("\x1B[1;1H{linebuf}");
+        }
+
+        if self.args.captions {
+            for (index, path) in paths.iter().enumerate() {
+                let (x, y) = grid.position(index);
+                let y = y + grid.thumbnail_height;
+                if self.args.inline {
+                    print!("\x1B[u");
+                    if y > 0 {
+                        print!("\x1B[{y}B");
+                    }
+                    if x > 0 {
+                        print!("\x1B[{x}C");
+                    }
+                } else {
+                    print!("\x1B[{};{}H", y + 1, x + 1);
+                }
+
+                let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+                let width = grid.thumbnail_width as usize;
+                let mut caption: String = name.chars()
+                    .map(|ch| if ch.is_control() { '?' } else { ch })
+                    .take(width)
+                    .collect();
+                if name.chars().count() > width {
+                    caption.pop();
+                    caption.push('…');
+                }
+                print!("\x1B[0m{caption}");
+            }
+        }
+        let _ = lock.flush();
+
+        self.linebuf = linebuf;
+
+        if self.slideshow {
+            self.wait(self.duration()).unwrap_or(Action::Next)
+        } else {
+            Action::Next
+        }
     }
 
-    match anim {
-        DecodedImage::Animated(width, height, frames) => {
-            if !frames.is_empty() {
+    fn show(&mut self, path: &Path) -> ImageResult<Action> {
+        let style = self.args.style;
+        let canvas_size = self.args.canvas_size;
+        let background_color = self.args.background_color;
+        let inline = self.args.inline;
+        let encoder = self.args.encoder;
+        let (cell_width, cell_height) = encoder.cell_size();
+
+        let anim = self.decode(path)?;
+        if self.use_alt_screen(anim.is_animated()) {
+            term::enter_alt_screen();
+        }
+
+        let dither = self.args.dither.resolve(anim.is_animated());
+
+        let mut renderer = self.renderer(dither);
+
+        let (image_width, image_height) = anim.size();
+        let mut term_canvas = renderer.new_canvas(image_width, image_height);
+
+        // Leave a row for the status line of animations.
+        let status = self.args.status && anim.is_animated() && !encoder.has_native_animation();
+        if let Some(term_canvas) = &mut term_canvas {
+            if status && canvas_size.is_window() && term_canvas.height() > cell_height {
+                *term_canvas = RgbaImage::new(term_canvas.width(), term_canvas.height() - cell_height);
+            }
+        }
+
+        let mut prev_frame = if let Some(term_canvas) = &term_canvas {
+            RgbaImage::new(term_canvas.width(), term_canvas.height())
+        } else {
+            RgbaImage::new(image_width, image_height)
+        };
+
+        let mut lock = std::io::stdout().lock();
+        let mut linebuf = std::mem::take(&mut self.linebuf);
+
+        self.begin(prev_frame.height().div_ceil(cell_height) + status as u32);
+
+        let passthrough = if encoder == Encoder::Iterm2 {
+            iterm2::passthrough_size(style, canvas_size, background_color, image_width, image_height)
+        } else {
+            None
+        };
+
+        let action = match (anim, passthrough) {
+            (anim, Some(size)) => {
+                // The terminal can decode, scale and animate the original file
+                // by itself.
+                let data: Arc<[u8]> = if is_stdin(path) {
+                    self.stdin_data()?
+                } else {
+                    std::fs::read(path)?.into()
+                };
+                iterm2::write_inline_image(&data, &size, &mut linebuf);
+                cursor_to_last_row(prev_frame.height().div_ceil(cell_height), &mut linebuf);
+                if inline {
+                    print!("\x1B[u{linebuf}");
+                } else {
+                    print!("\x1B[1;1H{linebuf}");
+                }
+                let _ = lock.flush();
+
+                if let StreamedImage::Animated(_, _, frames, file_loop_count) = anim {
+                    let total: Duration = frames
+                        .map(|frame| frame.map(|frame| Duration::from(frame.delay())))
+                        .sum::<ImageResult<_>>()?;
+                    let loops = self.loop_count(file_loop_count);
+                    self.wait_for(loops.map(|count| total * count)).unwrap_or(Action::Next)
+                } else if self.slideshow {
+                    self.wait(self.duration()).unwrap_or(Action::Next)
+                } else {
+                    Action::Next
+                }
+            }
+            (StreamedImage::Animated(width, height, mut frames, file_loop_count), None) => 'anim: {
                 let mut frame_canvas = RgbaImage::new(width, height);
-                let mut loop_count = if args.loop_count < 0 { None } else { Some(args.loop_count )};
+                let loop_count = self.loop_count(file_loop_count);
                 let mut timestamp = Instant::now();
 
-                while loop_count.unwrap_or(1) > 0 && run_anim.load(Ordering::Relaxed) {
-                    for frame in &frames {
-                        if !run_anim.load(Ordering::Relaxed) {
+                if encoder.has_native_animation() {
+                    // Upload all frames once and let the terminal play them.
+                    let mut kitty = KittyImage::new(KittyImage::default_id(), self.args.compress);
+                    let mut total = Duration::ZERO;
+                    let mut first = true;
+
+                    for frame in frames.by_ref() {
+                        if !self.run_anim.load(Ordering::Relaxed) {
                             break;
                         }
 
-                        let duration: Duration = frame.delay().into();
+                        let frame = frame?;
+                        compose_frame(&mut frame_canvas, &frame, background_color)?;
+                        let duration = self.frame_delay(frame.delay().into()).div_f64(self.args.speed);
+                        total += duration;
 
-                        let frame_image = frame.buffer();
-                        if let Color::Solid(rgb) = background_color {
-                            let rgba = rgb.to_rgba();
-                            for pixel in frame_canvas.pixels_mut() {
-                                *pixel = rgba;
-                            }
-                            imageops::overlay(&mut frame_canvas, frame_image, frame.left() as i64, frame.top() as i64);
+                        if let Some(term_canvas) = &mut term_canvas {
+                            renderer.paint(&frame_canvas, term_canvas);
+                            kitty.add_frame(term_canvas, duration, &mut linebuf);
                         } else {
-                            if frame_image.width() != frame_canvas.width() ||
-                               frame_image.height() != frame_canvas.height() ||
-                               frame.left() != 0 || frame.top() != 0
-                            {
-                                frame_canvas.fill(0);
+                            kitty.add_frame(&frame_canvas, duration, &mut linebuf);
+                        }
+
+                        if first {
+                            if inline {
+                                print!("\x1B[u");
+                            } else {
+                                print!("\x1B[1;1H");
                             }
-                            frame_canvas.copy_from(frame_image, frame.left(), frame.top())?;
+                            first = false;
                         }
+                        print!("{linebuf}");
+                    }
 
-                        let term_size = term_size::dimensions();
+                    linebuf.clear();
+                    if loop_count != Some(0) {
+                        kitty.play(loop_count, &mut linebuf);
+                    }
+                    cursor_to_last_row(prev_frame.height().div_ceil(cell_height), &mut linebuf);
+                    print!("{linebuf}");
+                    let _ = lock.flush();
 
-                        if let Some(term_canvas) = &mut term_canvas {
-                            let full_width;
-                            if canvas_size.is_window() {
-                                full_width = true;
-                                if let Some((term_width, term_height)) = term_size {
-                                    if term_width != term_canvas.width() as usize || (term_height * 2) != term_canvas.height() as usize {
-                                        match background_color {
-                                            Color::Transparent => {
-                                                *term_canvas = RgbaImage::new(term_width as u32, term_height as u32 * 2);
-                                            }
-                                            Color::Solid(rgb) => {
-                                                *term_canvas = RgbaImage::from_pixel(term_width as u32, term_height as u32 * 2, rgb.to_rgba());
-                                            }
-                                        }
-                                        prev_frame = RgbaImage::new(term_canvas.width(), term_canvas.height());
-                                        print!("\x1B[2J");
-                                    } else {
-                                        fill_color(term_canvas, background_color);
-                                    }
-                                } else {
-                                    fill_color(term_canvas, background_color);
+                    // Nothing to do but wait for the animation to end.
+                    let action = self.wait_for(loop_count.map(|count| total * count)).unwrap_or(Action::Next);
+
+                    linebuf.clear();
+                    kitty.stop(&mut linebuf);
+                    if self.slideshow {
+                        kitty.delete(&mut linebuf);
+                    }
+                    print!("{linebuf}");
+                    break 'anim action;
+                }
+
+                let window_size = |renderer: &Renderer| renderer.window_size().map(|(width, height)|
+                    if status { (width, height.saturating_sub(1).max(1)) } else { (width, height) });
+
+                let mut playback = Playback::new(width, height, frames, background_color);
+                let mut loops_left = loop_count;
+                let mut index = 0;
+                let mut shown: Option<usize> = None;
+                let mut paused = false;
+                let mut speed = self.args.speed;
+                let min_frame_time = self.min_frame_time();
+
+                let mut term_size = window_size(&renderer);
+
+                while loops_left != Some(0) && self.run_anim.load(Ordering::Relaxed) {
+                    // Without SIGWINCH the window size is checked for every frame.
+                    let mut resized = false;
+                    if !self.watch_resize || term::take_resized() {
+                        let new_size = window_size(&renderer);
+                        resized = new_size != term_size;
+                        term_size = new_size;
+                    }
+
+                    let mut full_width = if let Some((term_width, _)) = term_size {
+                        prev_frame.width().div_ceil(cell_width) as usize >= term_width
+                    } else {
+                        true
+                    };
+
+                    if let Some(term_canvas) = &mut term_canvas {
+                        if canvas_size.is_window() {
+                            full_width = true;
+                            if let Some((term_width, term_height)) = term_size {
+                                let term_width  = term_width  as u32 * cell_width;
+                                let term_height = term_height as u32 * cell_height;
+                                if term_width != term_canvas.width() || term_height != term_canvas.height() {
+                                    *term_canvas = RgbaImage::new(term_width, term_height);
+                                    resized = true;
                                 }
-                            } else {
-                                full_width = if let Some((term_width, _)) = term_size {
-                                    term_canvas.width() as usize >= term_width
-                                } else {
-                                    true
-                                };
-                                fill_color(term_canvas, background_color);
                             }
+                        }
+                    }
 
-                            style.paint(&frame_canvas, term_canvas, filter);
+                    if resized {
+                        prev_frame = match &term_canvas {
+                            Some(term_canvas) => RgbaImage::new(term_canvas.width(), term_canvas.height()),
+                            None => RgbaImage::new(width, height),
+                        };
+                        renderer.reset();
+                        self.begin(prev_frame.height().div_ceil(cell_height) + status as u32);
+                        playback.invalidate();
+                        shown = None;
+                    }
 
-                            image_to_ansi_into(&prev_frame, term_canvas, alpha_threshold, full_width, &mut linebuf);
-                            std::mem::swap(&mut prev_frame, term_canvas);
-                        } else {
-                            let full_width = if let Some((term_width, _)) = term_size {
-                                frame_canvas.width() as usize >= term_width
-                            } else {
-                                true
-                            };
-                            image_to_ansi_into(&prev_frame, &frame_canvas, alpha_threshold, full_width, &mut linebuf);
-                            std::mem::swap(&mut prev_frame, &mut frame_canvas);
+                    // Showing the frame after the last shown one can reuse the escape
+                    // sequences of the last time.
+                    let sequential = match shown {
+                        Some(shown) if index == 0 => playback.frame_count() == Some(shown + 1),
+                        Some(shown) => shown + 1 == index,
+                        None => false,
+                    };
+                    let encoded = sequential && match playback.encoded(index, full_width) {
+                        Some(encoded) => {
+                            linebuf.clear();
+                            linebuf.push_str(encoded);
+                            true
                         }
+                        None => false,
+                    };
 
-                        if inline {
-                            print!("\x1B[u{linebuf}");
-                        } else {
-                            print!("\x1B[1;1H{linebuf}");
+                    let last = loops_left == Some(1) && playback.frame_count() == Some(index + 1);
+                    let Some(delay) = playback.compose(index, &renderer, term_canvas.as_mut())? else {
+                        if index == 0 {
+                            // No frames at all.
+                            break;
+                        }
+                        if !paused && loops_left == Some(1) && shown != Some(index - 1) {
+                            // The last frame was dropped, but it should stay on screen.
+                            index -= 1;
+                            continue;
+                        }
+                        index = 0;
+                        if !paused {
+                            if let Some(loops_left) = &mut loops_left {
+                                *loops_left -= 1;
+                            }
                         }
-                        let _ = lock.flush();
+                        continue;
+                    };
 
-                        let now = Instant::now();
+                    let delay = self.frame_delay(delay);
+                    let duration = delay.div_f64(speed);
+                    let end = timestamp + duration;
 
-                        let elapsed = if timestamp > now {
-                            // This would mean that it slept shorter than requested, but didn't
-                            // signal any error!
-                            Duration::ZERO
+                    // Drop frames whose time is already over when painting can't keep up,
+                    // unless it is so far behind that it's better to start over from here.
+                    // They are composed, but not painted.
+                    let now = Instant::now();
+                    if !paused && !last && shown.is_some() && !duration.is_zero() && now >= end {
+                        if now - end < MAX_LAG {
+                            timestamp = end;
+                            index += 1;
+                            continue;
+                        }
+                        timestamp = now;
+                    }
+
+                    let Some((painted, _)) = playback.frame(index, &renderer, term_canvas.as_mut())? else {
+                        // Can't happen, the frame was just composed.
+                        break;
+                    };
+
+                    if !encoded {
+                        renderer.encode_into(&prev_frame, painted, full_width, &mut linebuf);
+                    }
+                    prev_frame.copy_from_slice(painted);
+                    if sequential && !encoded {
+                        playback.set_encoded(index, full_width, &linebuf);
+                    }
+                    shown = Some(index);
+
+                    if inline {
+                        print!("\x1B[u{linebuf}");
+                    } else {
+                        print!("\x1B[1;1H{linebuf}");
+                    }
+                    if status {
+                        self.print_status(prev_frame.height().div_ceil(cell_height), index, playback.frame_count(), delay, speed, paused);
+                    }
+                    let _ = lock.flush();
+
+                    let now = Instant::now();
+                    timestamp += duration;
+                    if paused || timestamp < now && now - timestamp >= MAX_LAG {
+                        timestamp = now;
+                    }
+                    let earliest = now + min_frame_time;
+
+                    let mut next = index + 1;
+                    loop {
+                        if term::resized() {
+                            // Show the frame again at the new size, for the rest of its time.
+                            next = index;
+                            if !paused {
+                                timestamp -= duration;
+                            }
+                            break;
+                        }
+
+                        let control = if paused {
+                            self.wait_control(Duration::from_secs(1))
                         } else {
-                            now - timestamp
+                            let now = Instant::now();
+                            let deadline = timestamp.max(earliest);
+                            if now >= deadline {
+                                break;
+                            }
+                            self.wait_control(deadline - now)
                         };
 
-                        timestamp += duration;
+                        match control {
+                            None | Some(Control::Other) => {}
+                            Some(Control::Action(action)) => break 'anim action,
+                            Some(Control::Pause) => {
+                                paused = !paused;
+                                if !paused {
+                                    timestamp = Instant::now();
+                                    break;
+                                }
+                            }
+                            Some(Control::StepForward) => {
+                                paused = true;
+                                break;
+                            }
+                            Some(Control::StepBack) => {
+                                paused = true;
+                                next = match (index, playback.frame_count()) {
+                                    (0, Some(frame_count)) => frame_count - 1,
+                                    (index, _) => index.saturating_sub(1),
+                                };
+                                break;
+                            }
+                            Some(Control::Faster) => {
+                                speed = (speed * 2.0).min(self.args.speed * MAX_SPEED);
+                            }
+                            Some(Control::Slower) => {
+                                speed = (speed / 2.0).max(self.args.speed / MAX_SPEED);
+                            }
+                            Some(Control::Restart) => {
+                                next = 0;
+                                loops_left = loop_count;
+                                timestamp = Instant::now();
+                                break;
+                            }
+                        }
 
-                        if duration > elapsed && !interruptable_sleep(duration - elapsed) {
-                            run_anim.store(false, Ordering::Relaxed);
-                            break;
+                        if status {
+                            self.print_status(prev_frame.height().div_ceil(cell_height), index, playback.frame_count(), delay, speed, paused);
+                            let _ = lock.flush();
                         }
                     }
+                    index = next;
+                }
+
+                Action::Next
+            }
+            (StreamedImage::Still(mut image), None) => {
+                if term_canvas.is_none() {
+                    renderer.quantize(&mut image);
+                }
+                let deadline = self.slideshow.then(|| Instant::now() + self.duration());
+
+                loop {
+                    if let Some(term_canvas) = &mut term_canvas {
+                        renderer.paint(&image, term_canvas);
+                        renderer.encode_into(&prev_frame, term_canvas, false, &mut linebuf);
+                    } else {
+                        renderer.encode_into(&prev_frame, &image, false, &mut linebuf);
+                    }
+
+                    if inline {
+                        print!("{linebuf}");
+                    } else {
+                        print!("\x1B[1;1H{linebuf}");
+                    }
+                    let _ = lock.flush();
 
-                    if let Some(loop_count) = &mut loop_count {
-                        *loop_count -= 1;
+                    if !self.slideshow && !self.args.watch {
+                        break Action::Next;
                     }
+
+                    if let Some(action) = self.wait_or_resize(deadline) {
+                        break action;
+                    }
+
+                    // Paint it again at the new window size.
+                    term_canvas = renderer.new_canvas(image_width, image_height);
+                    prev_frame = match &term_canvas {
+                        Some(term_canvas) => RgbaImage::new(term_canvas.width(), term_canvas.height()),
+                        None => RgbaImage::new(image_width, image_height),
+                    };
+                    renderer.reset();
+                    self.begin(prev_frame.height().div_ceil(cell_height));
                 }
             }
+        };
+
+        self.linebuf = linebuf;
+        Ok(action)
+    }
+}
+
+fn main() -> ImageResult<()> {
+    let mut args = Args::parse();
+
+    for (name, value) in [("--speed", Some(args.speed)), ("--fps", args.fps), ("--max-fps", args.max_fps)] {
+        if value.is_some_and(|value| !(value.is_finite() && value > 0.0)) {
+            Args::command()
+                .error(ErrorKind::ValueValidation, format!("{name} has to be a positive number"))
+                .exit();
         }
-        DecodedImage::Still(image) => {
-            if let Some(term_canvas) = &mut term_canvas {
-                style.paint(&image, term_canvas, filter);
-                image_to_ansi_into(&prev_frame, &term_canvas, alpha_threshold, false, &mut linebuf);
-            } else {
-                image_to_ansi_into(&prev_frame, &image, alpha_threshold, false, &mut linebuf);
+    }
+
+    let run_anim = Arc::new(AtomicBool::new(true));
+    let endl = args.line_end.as_str();
+    let color_depth = if args.color_depth.is_auto() {
+        term::detect_color_depth(term::DEFAULT_QUERY_TIMEOUT)
+    } else {
+        args.color_depth
+    };
+
+    {
+        let run_anim = run_anim.clone();
+        let _ = ctrlc::set_handler(move || {
+            run_anim.store(false, Ordering::Relaxed);
+        });
+    }
+
+    let mut paths = expand_paths(std::mem::take(&mut args.paths));
+    if paths.is_empty() {
+        if std::io::stdin().is_terminal() {
+            Args::command()
+                .error(ErrorKind::MissingRequiredArgument, "no image files given and stdin is a terminal")
+                .exit();
+        }
+        paths.push(PathBuf::from("-"));
+    }
+    let repeat = args.repeat;
+    let grid = args.grid;
+
+    // Don't wait until the first image is decoded to report a missing file.
+    if paths.len() == 1 && !grid && !is_stdin(&paths[0]) {
+        std::fs::metadata(&paths[0])?;
+    }
+
+    let export = args.output.is_some() || args.format != Format::Ansi;
+    let cell_aspect = match args.cell_aspect {
+        // Don't ask the terminal about images that aren't shown on it.
+        CellAspect::Auto if export || !std::io::stdout().is_terminal() => {
+            let (width, height) = term::cell_pixel_size().unwrap_or(term::DEFAULT_CELL_PIXEL_SIZE);
+            width as f64 / height as f64
+        }
+        CellAspect::Auto => term::detect_cell_aspect(term::DEFAULT_QUERY_TIMEOUT),
+        CellAspect::Ratio(ratio) => ratio,
+    };
+    if export && args.encoder.is_graphics() {
+        // Graphics protocols can't be written as text.
+        args.encoder = Encoder::HalfBlock;
+    }
+
+    let guard = (!export).then(|| TerminalGuard::new(endl));
+
+    let mut viewer = Viewer {
+        args,
+        color_depth,
+        cell_aspect,
+        run_anim,
+        // Keys are read from the terminal even when an image is piped in.
+        input: if export { None } else { Input::new(libc::STDIN_FILENO).or_else(Input::tty) },
+        slideshow: false,
+        first: true,
+        linebuf: String::new(),
+        errors: Vec::new(),
+        stdin: None,
+        watch_resize: !export && term::watch_resize(),
+    };
+
+    if export {
+        let output = viewer.args.output.clone().unwrap_or_else(|| PathBuf::from("-"));
+        return viewer.export(&output, &paths);
+    }
+
+    let layout = if grid {
+        let renderer = viewer.renderer(viewer.args.dither);
+        Some(viewer.grid(&viewer.grid_canvas(&renderer), renderer.pixel_aspect()))
+    } else {
+        None
+    };
+    let count = if let Some(layout) = &layout {
+        paths.len().div_ceil(layout.per_page())
+    } else {
+        paths.len()
+    };
+    let slideshow = count > 1;
+    viewer.slideshow = slideshow;
+
+    let mut result = Ok(());
+    let mut index = 0;
+    while index < count && viewer.run_anim.load(Ordering::Relaxed) {
+        let shown = if let Some(layout) = &layout {
+            let start = index * layout.per_page();
+            let end = (start + layout.per_page()).min(paths.len());
+            Ok(viewer.show_grid(layout, &paths[start..end]))
+        } else {
+            viewer.show(&paths[index])
+        };
+
+        let action = match shown {
+            Ok(action) => action,
+            Err(error) if slideshow => {
+                eprint!("\x1B[0m{}: {error}{endl}", paths[index].display());
+                if term::is_alt_screen() {
+                    // Report it again once the screen is restored.
+                    viewer.errors.push(format!("{}: {error}", paths[index].display()));
+                }
+                viewer.first = true;
+                viewer.wait(viewer.duration()).unwrap_or(Action::Next)
+            }
+            Err(error) => {
+                result = Err(error);
+                break;
             }
+        };
 
-            if inline {
-                print!("{linebuf}");
-            } else {
-                print!("\x1B[1;1H{linebuf}");
+        match action {
+            Action::Quit => break,
+            Action::Next => {
+                index += 1;
+                if index == count && repeat {
+                    index = 0;
+                }
+            }
+            Action::Prev => {
+                if index > 0 {
+                    index -= 1;
+                } else if repeat {
+                    index = count - 1;
+                }
             }
-            let _ = lock.flush();
         }
     }
 
-    // CSI 0 m        Reset or normal, all attributes become turned off
-    // CSI ?  7 h     Auto-Wrap Mode (DECAWM), VT100
-    // CSI ? 25 h     Show cursor (DECTCEM), VT220
-    print!("\x1B[0m\x1B[?25h\x1B[?7h{endl}");
+    // Restores the terminal settings.
+    let errors = std::mem::take(&mut viewer.errors);
+    drop(viewer);
+    drop(guard);
 
-    #[cfg(target_family = "unix")]
-    if let Some(term) = term {
-        term.c_lflag |= libc::ECHO;
-        unsafe { libc::tcsetattr(libc::STDIN_FILENO, 0, term); }
+    for error in errors {
+        eprint!("{error}{endl}");
     }
 
-    Ok(())
+    result
 }
@@ -0,0 +1,444 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use image::{Rgb, Rgba, RgbaImage};
+
+use crate::color_depth::ColorDepth;
+
+/// Bits per channel of the buckets of the nearest color lookup table.
+const LUT_BITS: u32 = 5;
+const LUT_LEVELS: usize = 1 << LUT_BITS;
+
+/// Bits of the index into the cache of looked up colors.
+const CACHE_BITS: u32 = 12;
+
+/// Default xterm colors of the 16 system colors. Terminals let the user
+/// change these, so they are only used when nothing better is available.
+const ANSI_16: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0xcd, 0x00, 0x00],
+    [0x00, 0xcd, 0x00],
+    [0xcd, 0xcd, 0x00],
+    [0x00, 0x00, 0xee],
+    [0xcd, 0x00, 0xcd],
+    [0x00, 0xcd, 0xcd],
+    [0xe5, 0xe5, 0xe5],
+    [0x7f, 0x7f, 0x7f],
+    [0xff, 0x00, 0x00],
+    [0x00, 0xff, 0x00],
+    [0xff, 0xff, 0x00],
+    [0x5c, 0x5c, 0xff],
+    [0xff, 0x00, 0xff],
+    [0x00, 0xff, 0xff],
+    [0xff, 0xff, 0xff],
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// Index of the first color cube entry in the xterm 256 color palette.
+/// The 16 system colors before it are skipped on purpose, because their
+/// actual values depend on the terminal theme.
+pub const XTERM_CUBE_OFFSET: u8 = 16;
+
+#[inline]
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_table() -> &'static [f32; 256] {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0.0; 256];
+        for (index, value) in table.iter_mut().enumerate() {
+            *value = srgb_to_linear(index as u8);
+        }
+        table
+    })
+}
+
+/// Converts a sRGB color into the perceptual OKLab color space.
+pub fn rgb_to_oklab(Rgb([r, g, b]): Rgb<u8>) -> [f32; 3] {
+    let table = linear_table();
+    linear_to_oklab([table[r as usize], table[g as usize], table[b as usize]])
+}
+
+const RGB_TO_LMS: [[f32; 3]; 3] = [
+    [0.412_221_46, 0.536_332_55, 0.051_445_995],
+    [0.211_903_5, 0.680_699_5, 0.107_396_96],
+    [0.088_302_46, 0.281_718_85, 0.629_978_7],
+];
+
+const LMS_TO_OKLAB: [[f32; 3]; 3] = [
+    [0.210_454_26, 0.793_617_8, -0.004_072_047],
+    [1.977_998_5, -2.428_592_2, 0.450_593_7],
+    [0.025_904_037, 0.782_771_77, -0.808_675_77],
+];
+
+#[inline]
+fn transform(matrix: &[[f32; 3]; 3], [x, y, z]: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * x + row[1] * y + row[2] * z)
+}
+
+/// Converts a linear RGB color (channels in `0.0..=1.0`) into OKLab.
+pub fn linear_to_oklab(rgb: [f32; 3]) -> [f32; 3] {
+    transform(&LMS_TO_OKLAB, transform(&RGB_TO_LMS, rgb).map(f32::cbrt))
+}
+
+/// Corners of a box in OKLab that contains every color of the box from
+/// `low` to `high` in linear RGB.
+fn oklab_bounds(low: [f32; 3], high: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    // All coefficients of RGB_TO_LMS are positive and the cube root grows
+    // monotonically, so the corners bound the cone responses.
+    let low = transform(&RGB_TO_LMS, low).map(f32::cbrt);
+    let high = transform(&RGB_TO_LMS, high).map(f32::cbrt);
+
+    let mut lab_low = [0.0; 3];
+    let mut lab_high = [0.0; 3];
+    for (row, coefficients) in LMS_TO_OKLAB.iter().enumerate() {
+        for (column, &coefficient) in coefficients.iter().enumerate() {
+            let a = coefficient * low[column];
+            let b = coefficient * high[column];
+            lab_low[row] += a.min(b);
+            lab_high[row] += a.max(b);
+        }
+    }
+    (lab_low, lab_high)
+}
+
+/// Squared distances from `point` to the nearest and the farthest point of
+/// the box from `low` to `high`.
+#[inline]
+fn box_distances(point: [f32; 3], low: [f32; 3], high: [f32; 3]) -> (f32, f32) {
+    let mut near = 0.0;
+    let mut far = 0.0;
+    for channel in 0..3 {
+        let below = low[channel] - point[channel];
+        let above = point[channel] - high[channel];
+        let outside = below.max(above).max(0.0);
+        let farthest = below.abs().max(above.abs());
+        near += outside * outside;
+        far += farthest * farthest;
+    }
+    (near, far)
+}
+
+/// Allows for rounding errors when comparing a distance with bounds.
+#[inline]
+fn with_leeway(distance: f32) -> f32 {
+    distance * 1.001 + 1e-6
+}
+
+#[inline]
+fn distance([l1, a1, b1]: [f32; 3], [l2, a2, b2]: [f32; 3]) -> f32 {
+    let dl = l1 - l2;
+    let da = a1 - a2;
+    let db = b1 - b2;
+    dl * dl + da * da + db * db
+}
+
+/// A fixed set of colors a terminal can display together with a lookup
+/// table for finding the perceptually nearest entry of any RGB color.
+///
+/// The table splits the RGB cube into buckets. For each bucket it lists the
+/// entries that can be the nearest one for some color of the bucket, so a
+/// lookup only has to compare the color with those few entries in OKLab.
+/// The buckets are filled on first use, and recent lookups are cached.
+#[derive(Debug)]
+pub struct Palette {
+    colors: Vec<Rgb<u8>>,
+    first_index: u8,
+    lab: Vec<[f32; 3]>,
+    buckets: Box<[OnceLock<Box<[Candidate]>>]>,
+    /// Entries of `1 << 32 | rgb << 8 | index`, with 0 meaning empty.
+    cache: Box<[AtomicU64]>,
+}
+
+/// An entry that can be the nearest one for the colors of a bucket, and
+/// how close it gets to the bucket.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    index: u8,
+    near: f32,
+}
+
+impl Palette {
+    /// Builds a palette from `colors`. The terminal color index of
+    /// `colors[n]` is `first_index + n`.
+    pub fn new(colors: Vec<Rgb<u8>>, first_index: u8) -> Self {
+        assert!(!colors.is_empty() && colors.len() + first_index as usize <= 256);
+
+        Self {
+            lab: colors.iter().map(|&color| rgb_to_oklab(color)).collect(),
+            colors,
+            first_index,
+            buckets: (0..LUT_LEVELS * LUT_LEVELS * LUT_LEVELS).map(|_| OnceLock::new()).collect(),
+            cache: (0..1 << CACHE_BITS).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    /// The entries that can be the nearest one for a color of `bucket`,
+    /// the closest ones first.
+    fn candidates(&self, bucket: usize) -> Box<[Candidate]> {
+        let table = linear_table();
+        let step = 256 / LUT_LEVELS;
+        let range = |level: usize| (table[level * step], table[level * step + step - 1]);
+        let (r_low, r_high) = range(bucket / (LUT_LEVELS * LUT_LEVELS));
+        let (g_low, g_high) = range(bucket / LUT_LEVELS % LUT_LEVELS);
+        let (b_low, b_high) = range(bucket % LUT_LEVELS);
+        let (low, high) = oklab_bounds([r_low, g_low, b_low], [r_high, g_high, b_high]);
+
+        // An entry can only be the nearest one if it may be closer than the
+        // farthest point of the bucket is to some other entry.
+        let distances: Vec<_> = self.lab.iter().map(|&entry| box_distances(entry, low, high)).collect();
+        let limit = with_leeway(distances.iter().map(|&(_, far)| far).fold(f32::INFINITY, f32::min));
+
+        let mut candidates: Vec<_> = distances.iter()
+            .enumerate()
+            .filter(|&(_, &(near, _))| near <= limit)
+            .map(|(index, &(near, _))| Candidate { index: index as u8, near })
+            .collect();
+        candidates.sort_by(|a, b| a.near.total_cmp(&b.near).then(a.index.cmp(&b.index)));
+        candidates.into_boxed_slice()
+    }
+
+    /// The palette used for `color_depth` or `None` for true color (and an
+    /// unresolved `Auto`).
+    pub fn for_color_depth(color_depth: ColorDepth) -> Option<&'static Palette> {
+        static XTERM_256: OnceLock<Palette> = OnceLock::new();
+        static ANSI_16_PALETTE: OnceLock<Palette> = OnceLock::new();
+        static ANSI_8_PALETTE: OnceLock<Palette> = OnceLock::new();
+        static MONOCHROME: OnceLock<Palette> = OnceLock::new();
+
+        match color_depth {
+            ColorDepth::TrueColor | ColorDepth::Auto => None,
+            ColorDepth::Xterm256 => Some(XTERM_256.get_or_init(|| {
+                let mut colors = Vec::with_capacity(240);
+                for r in CUBE_LEVELS {
+                    for g in CUBE_LEVELS {
+                        for b in CUBE_LEVELS {
+                            colors.push(Rgb([r, g, b]));
+                        }
+                    }
+                }
+                for index in 0..24 {
+                    let value = 8 + index * 10;
+                    colors.push(Rgb([value, value, value]));
+                }
+                Palette::new(colors, XTERM_CUBE_OFFSET)
+            })),
+            ColorDepth::Ansi16 => Some(ANSI_16_PALETTE.get_or_init(||
+                Palette::new(ANSI_16.iter().map(|&rgb| Rgb(rgb)).collect(), 0))),
+            ColorDepth::Ansi8 => Some(ANSI_8_PALETTE.get_or_init(||
+                Palette::new(ANSI_16[..8].iter().map(|&rgb| Rgb(rgb)).collect(), 0))),
+            ColorDepth::Monochrome => Some(MONOCHROME.get_or_init(||
+                Palette::new(vec![Rgb([0, 0, 0]), Rgb([255, 255, 255])], 0))),
+        }
+    }
+
+    #[inline]
+    pub fn colors(&self) -> &[Rgb<u8>] {
+        &self.colors
+    }
+
+    /// Whether `color` is exactly one of the palette colors.
+    #[inline]
+    pub fn contains(&self, color: Rgb<u8>) -> bool {
+        self.colors.contains(&color)
+    }
+
+    /// Rough distance between neighbouring palette colors per channel. Used
+    /// to scale the threshold of ordered dithering.
+    #[inline]
+    pub fn spread(&self) -> f32 {
+        256.0 / (self.colors.len() as f32).cbrt()
+    }
+
+    /// Position of the perceptually nearest color in `colors()`. Of equally
+    /// near colors the first one is picked.
+    pub fn nearest(&self, color: Rgb<u8>) -> usize {
+        let Rgb([r, g, b]) = color;
+        let rgb = u32::from_be_bytes([0, r, g, b]);
+        let key = 1 << 24 | rgb as u64;
+        let slot = &self.cache[(rgb.wrapping_mul(0x9E37_79B1) >> (32 - CACHE_BITS)) as usize];
+        let entry = slot.load(Ordering::Relaxed);
+        if entry >> 8 == key {
+            return (entry & 0xFF) as usize;
+        }
+
+        let shift = 8 - LUT_BITS;
+        let bucket = ((r >> shift) as usize * LUT_LEVELS + (g >> shift) as usize) * LUT_LEVELS + (b >> shift) as usize;
+        let candidates = self.buckets[bucket].get_or_init(|| self.candidates(bucket));
+
+        let index = if let [candidate] = &candidates[..] {
+            candidate.index as usize
+        } else {
+            let color = rgb_to_oklab(color);
+            let mut best_index = usize::MAX;
+            let mut best_distance = f32::INFINITY;
+            for candidate in candidates.iter() {
+                if candidate.near > with_leeway(best_distance) {
+                    // The remaining ones are all farther away.
+                    break;
+                }
+                let index = candidate.index as usize;
+                let dist = distance(self.lab[index], color);
+                if dist < best_distance || (dist == best_distance && index < best_index) {
+                    best_distance = dist;
+                    best_index = index;
+                }
+            }
+            best_index
+        };
+
+        slot.store(key << 8 | index as u64, Ordering::Relaxed);
+        index
+    }
+
+    /// The terminal color index (as used in `38;5;n` or to derive `30 + n`)
+    /// of the nearest palette entry.
+    #[inline]
+    pub fn terminal_index(&self, color: Rgb<u8>) -> u8 {
+        self.first_index + self.nearest(color) as u8
+    }
+
+    #[inline]
+    pub fn quantize(&self, color: Rgb<u8>) -> Rgb<u8> {
+        self.colors[self.nearest(color)]
+    }
+}
+
+/// Replaces every pixel of `image` with the nearest color the terminal can
+/// display at `color_depth`, so that equal output colors compare as equal
+/// pixels when diffing frames.
+///
+/// In monochrome mode unlit (black) pixels are made fully transparent, so
+/// that they are rendered using the terminal's own background.
+pub fn quantize_image(image: &mut RgbaImage, color_depth: ColorDepth, alpha_threshold: u8) {
+    let Some(palette) = Palette::for_color_depth(color_depth) else {
+        return;
+    };
+
+    let monochrome = color_depth.is_monochrome();
+    for pixel in image.pixels_mut() {
+        let Rgba([r, g, b, a]) = *pixel;
+        if a < alpha_threshold {
+            continue;
+        }
+        store_quantized(pixel, palette.quantize(Rgb([r, g, b])), monochrome);
+    }
+}
+
+/// Writes the already quantized `color` into `pixel`, keeping its alpha.
+#[inline]
+pub(crate) fn store_quantized(pixel: &mut Rgba<u8>, color: Rgb<u8>, monochrome: bool) {
+    let Rgb([r, g, b]) = color;
+    if monochrome && r == 0 {
+        *pixel = Rgba([0, 0, 0, 0]);
+    } else {
+        *pixel = Rgba([r, g, b, pixel[3]]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compares `color` with every entry.
+    fn nearest_exhaustive(palette: &Palette, color: Rgb<u8>) -> usize {
+        let color = rgb_to_oklab(color);
+        let mut best_index = 0;
+        let mut best_distance = f32::INFINITY;
+        for (index, &entry) in palette.lab.iter().enumerate() {
+            let dist = distance(entry, color);
+            if dist < best_distance {
+                best_distance = dist;
+                best_index = index;
+            }
+        }
+        best_index
+    }
+
+    fn palettes() -> Vec<&'static Palette> {
+        [ColorDepth::Xterm256, ColorDepth::Ansi16, ColorDepth::Ansi8, ColorDepth::Monochrome]
+            .into_iter()
+            .filter_map(Palette::for_color_depth)
+            .collect()
+    }
+
+    #[test]
+    fn nearest_is_exact() {
+        let custom = Palette::new(vec![Rgb([12, 200, 40]), Rgb([13, 201, 41]), Rgb([128, 0, 255]), Rgb([250, 250, 240])], 0);
+        let levels: Vec<u8> = (0..=255).step_by(7).chain([8, 9, 255]).collect();
+        for palette in palettes().into_iter().chain([&custom]) {
+            for &r in &levels {
+                for &g in &levels {
+                    for &b in &levels {
+                        let color = Rgb([r, g, b]);
+                        assert_eq!(palette.nearest(color), nearest_exhaustive(palette, color), "{color:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_finds_palette_colors() {
+        for palette in palettes() {
+            for (index, &color) in palette.colors().iter().enumerate() {
+                assert_eq!(palette.nearest(color), index);
+                assert!(palette.contains(color));
+            }
+        }
+    }
+
+    #[test]
+    fn terminal_indices() {
+        let xterm = Palette::for_color_depth(ColorDepth::Xterm256).unwrap();
+        assert_eq!(xterm.terminal_index(Rgb([255, 0, 0])), 196);
+        assert_eq!(xterm.terminal_index(Rgb([0, 0, 0])), 16);
+        assert_eq!(xterm.terminal_index(Rgb([128, 128, 128])), 244);
+        assert_eq!(xterm.quantize(Rgb([250, 3, 4])), Rgb([255, 0, 0]));
+
+        let ansi16 = Palette::for_color_depth(ColorDepth::Ansi16).unwrap();
+        assert_eq!(ansi16.terminal_index(Rgb([255, 0, 0])), 9);
+        assert_eq!(ansi16.terminal_index(Rgb([200, 0, 0])), 1);
+
+        let ansi8 = Palette::for_color_depth(ColorDepth::Ansi8).unwrap();
+        assert_eq!(ansi8.terminal_index(Rgb([255, 0, 0])), 1);
+
+        assert!(Palette::for_color_depth(ColorDepth::TrueColor).is_none());
+    }
+
+    #[test]
+    fn quantize_image_keeps_transparent_pixels() {
+        let mut image = RgbaImage::from_vec(3, 1, vec![
+            250, 3, 4, 255,
+            10, 20, 30, 0,
+            255, 255, 255, 200,
+        ]).unwrap();
+        quantize_image(&mut image, ColorDepth::Xterm256, 127);
+        assert_eq!(image.pixels().copied().collect::<Vec<_>>(), [
+            Rgba([255, 0, 0, 255]),
+            Rgba([10, 20, 30, 0]),
+            Rgba([255, 255, 255, 200]),
+        ]);
+    }
+
+    #[test]
+    fn quantize_image_monochrome_clears_unlit_pixels() {
+        let mut image = RgbaImage::from_vec(2, 1, vec![
+            20, 20, 20, 255,
+            230, 230, 230, 255,
+        ]).unwrap();
+        quantize_image(&mut image, ColorDepth::Monochrome, 127);
+        assert_eq!(image.pixels().copied().collect::<Vec<_>>(), [
+            Rgba([0, 0, 0, 0]),
+            Rgba([255, 255, 255, 255]),
+        ]);
+    }
+}
@@ -0,0 +1,335 @@
+use std::time::Duration;
+
+use image::error::ImageResult;
+use image::RgbaImage;
+
+use crate::color::Color;
+use crate::decode::FrameStream;
+use crate::renderer::{compose_frame, Renderer};
+
+/// Bytes of painted frames and their escape sequences to keep for
+/// replaying an animation.
+pub const MAX_CACHE_SIZE: usize = 256 << 20;
+
+/// A painted frame kept for replaying.
+struct CachedFrame {
+    canvas: RgbaImage,
+    delay: Duration,
+    /// Escape sequences for the transition from the previous frame.
+    encoded: Option<String>,
+}
+
+/// Paints the frames of an animation in any order. Painted frames are
+/// kept while they fit into [`MAX_CACHE_SIZE`], so that loops and steps
+/// back are replayed without decoding and painting them again. Otherwise
+/// the file is decoded again from the start.
+pub struct Playback {
+    frames: FrameStream,
+    background_color: Color,
+    frame_canvas: RgbaImage,
+    painted: RgbaImage,
+    /// Index of the frame that `frames` yields next.
+    next: usize,
+    frame_count: Option<usize>,
+    cache: Vec<CachedFrame>,
+    cache_size: usize,
+    caching: bool,
+    cache_enabled: bool,
+    encoded_full_width: bool,
+    /// Delay of the frame in `frame_canvas` if it was composed by
+    /// [`compose`](Self::compose), but not painted yet.
+    unpainted: Option<Duration>,
+}
+
+impl Playback {
+    pub fn new(width: u32, height: u32, frames: FrameStream, background_color: Color) -> Self {
+        Self {
+            frames,
+            background_color,
+            frame_canvas: RgbaImage::new(width, height),
+            painted: RgbaImage::new(width, height),
+            next: 0,
+            frame_count: None,
+            cache: Vec::new(),
+            cache_size: 0,
+            caching: true,
+            cache_enabled: true,
+            encoded_full_width: false,
+            unpainted: None,
+        }
+    }
+
+    /// Number of frames, once the end was reached.
+    #[inline]
+    pub fn frame_count(&self) -> Option<usize> {
+        self.frame_count
+    }
+
+    /// Forgets the painted frames, e.g. because the canvas was resized.
+    pub fn invalidate(&mut self) {
+        self.cache = Vec::new();
+        self.cache_size = 0;
+        self.caching = false;
+    }
+
+    /// Composes frame `index` and returns its delay, or `None` if the
+    /// animation has fewer frames. Painting it is left to
+    /// [`frame`](Self::frame), so a frame that is already too late can be
+    /// dropped without that cost. Frames skipped on the way are only painted
+    /// for the cache.
+    pub fn compose(&mut self, index: usize, renderer: &Renderer, mut canvas: Option<&mut RgbaImage>) -> ImageResult<Option<Duration>> {
+        if let Some(frame) = self.cache.get(index) {
+            return Ok(Some(frame.delay));
+        }
+
+        if self.frame_count.is_some_and(|frame_count| index >= frame_count) {
+            return Ok(None);
+        }
+
+        if let Some(delay) = self.unpainted {
+            if index + 1 == self.next {
+                return Ok(Some(delay));
+            }
+        }
+
+        if index < self.next {
+            self.frames.rewind();
+            self.next = 0;
+            self.unpainted = None;
+        }
+
+        if self.next == 0 && self.cache.is_empty() {
+            self.caching = self.cache_enabled;
+        }
+
+        let mut delay = Duration::ZERO;
+        while self.next <= index {
+            let Some(frame) = self.frames.next() else {
+                self.frame_count = Some(self.next);
+                return Ok(None);
+            };
+            let frame = frame?;
+            if self.unpainted.take().is_some() && self.caching {
+                // The frame before was dropped, which leaves a gap in the
+                // cache. Keeping frames starts over with the next pass.
+                self.invalidate();
+            }
+            delay = frame.delay().into();
+            compose_frame(&mut self.frame_canvas, &frame, self.background_color)?;
+            self.next += 1;
+
+            if self.caching && self.next <= index {
+                self.paint(renderer, canvas.as_deref_mut(), delay);
+            }
+        }
+
+        self.unpainted = Some(delay);
+        Ok(Some(delay))
+    }
+
+    /// Paints frame `index` onto `canvas` like [`Renderer::paint`], or
+    /// quantizes it if there is no canvas. Returns the painted frame and
+    /// its delay, or `None` if the animation has fewer frames.
+    pub fn frame<'a>(&'a mut self, index: usize, renderer: &Renderer, mut canvas: Option<&'a mut RgbaImage>) -> ImageResult<Option<(&'a RgbaImage, Duration)>> {
+        if index < self.cache.len() {
+            let frame = &self.cache[index];
+            return Ok(Some((&frame.canvas, frame.delay)));
+        }
+
+        let Some(delay) = self.compose(index, renderer, canvas.as_deref_mut())? else {
+            return Ok(None);
+        };
+        self.unpainted = None;
+        self.paint(renderer, canvas.as_deref_mut(), delay);
+
+        let painted = match canvas {
+            Some(canvas) => canvas,
+            None => &self.painted,
+        };
+        Ok(Some((painted, delay)))
+    }
+
+    /// Paints the frame in `frame_canvas` and keeps it if frames are cached.
+    fn paint(&mut self, renderer: &Renderer, canvas: Option<&mut RgbaImage>, delay: Duration) {
+        let painted = if let Some(canvas) = canvas {
+            renderer.paint(&self.frame_canvas, canvas);
+            canvas
+        } else {
+            self.painted.copy_from_slice(&self.frame_canvas);
+            renderer.quantize(&mut self.painted);
+            &self.painted
+        };
+
+        if self.caching {
+            if self.cache_size + painted.len() <= MAX_CACHE_SIZE {
+                self.cache_size += painted.len();
+                self.cache.push(CachedFrame { canvas: painted.clone(), delay, encoded: None });
+            } else {
+                self.invalidate();
+                self.cache_enabled = false;
+            }
+        }
+    }
+
+    /// The kept escape sequences for showing frame `index` right after the
+    /// frame before it, encoded with the same `full_width`.
+    #[inline]
+    pub fn encoded(&self, index: usize, full_width: bool) -> Option<&str> {
+        if full_width != self.encoded_full_width {
+            return None;
+        }
+        self.cache.get(index)?.encoded.as_deref()
+    }
+
+    /// Keeps the escape sequences for showing frame `index` right after the
+    /// frame before it, if the frame is kept.
+    pub fn set_encoded(&mut self, index: usize, full_width: bool, encoded: &str) {
+        if full_width != self.encoded_full_width {
+            self.encoded_full_width = full_width;
+            for frame in &mut self.cache {
+                if let Some(encoded) = frame.encoded.take() {
+                    self.cache_size -= encoded.len();
+                }
+            }
+        }
+
+        if self.cache_size + encoded.len() > MAX_CACHE_SIZE {
+            return;
+        }
+        if let Some(frame) = self.cache.get_mut(index) {
+            self.cache_size += encoded.len();
+            frame.encoded = Some(encoded.to_owned());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    use image::io::Reader as ImageReader;
+    use image::Rgba;
+
+    use super::*;
+    use crate::color_depth::ColorDepth;
+    use crate::decode::{decode_streaming, StreamedImage};
+    use crate::style::Style;
+
+    /// A 2x2 GIF whose frames are black, white and black again.
+    fn playback() -> Playback {
+        let mut data = Vec::new();
+        {
+            let mut encoder = gif::Encoder::new(&mut data, 2, 2, &[0, 0, 0, 255, 255, 255]).unwrap();
+            for color in [0, 1, 0] {
+                let frame = gif::Frame { width: 2, height: 2, delay: 10, buffer: vec![color; 4].into(), ..Default::default() };
+                encoder.write_frame(&frame).unwrap();
+            }
+        }
+        let data: Arc<[u8]> = data.into();
+        let open = move || Ok(ImageReader::new(Cursor::new(data.clone())).with_guessed_format()?);
+        match decode_streaming(open).unwrap() {
+            StreamedImage::Animated(width, height, frames, _) => Playback::new(width, height, frames, Color::Transparent),
+            StreamedImage::Still(_) => panic!("not an animation"),
+        }
+    }
+
+    fn renderer() -> Renderer {
+        Renderer::new()
+            .style(Style::Contain)
+            .cell_aspect(0.5)
+            .color_depth(ColorDepth::TrueColor)
+    }
+
+    #[test]
+    fn frames_are_kept_for_replaying() {
+        let mut playback = playback();
+        let renderer = renderer();
+
+        for (index, value) in [0, 255, 0].into_iter().enumerate() {
+            let (painted, delay) = playback.frame(index, &renderer, None).unwrap().unwrap();
+            assert_eq!(*painted.get_pixel(0, 0), Rgba([value, value, value, 255]));
+            assert_eq!(delay, Duration::from_millis(100));
+        }
+        assert!(playback.frame(3, &renderer, None).unwrap().is_none());
+        assert_eq!(playback.frame_count(), Some(3));
+        assert_eq!(playback.cache.len(), 3);
+
+        let (painted, _) = playback.frame(1, &renderer, None).unwrap().unwrap();
+        assert_eq!(*painted.get_pixel(1, 1), Rgba([255, 255, 255, 255]));
+        assert_eq!(playback.next, 3);
+    }
+
+    #[test]
+    fn resize_paints_the_frames_again() {
+        let mut playback = playback();
+        let renderer = renderer();
+
+        let mut small = RgbaImage::new(2, 2);
+        for index in 0..2 {
+            playback.frame(index, &renderer, Some(&mut small)).unwrap();
+        }
+        playback.set_encoded(1, false, "small");
+
+        playback.invalidate();
+        assert!(playback.cache.is_empty());
+        assert_eq!(playback.encoded(1, false), None);
+
+        let mut large = RgbaImage::new(4, 4);
+        let (painted, _) = playback.frame(1, &renderer, Some(&mut large)).unwrap().unwrap();
+        assert_eq!(painted.dimensions(), (4, 4));
+        assert_eq!(*painted.get_pixel(3, 3), Rgba([255, 255, 255, 255]));
+
+        // Going back starts over from the first frame, which keeps the
+        // frames again, now at the new size.
+        assert_eq!(playback.cache.len(), 2);
+        assert!(playback.cache.iter().all(|frame| frame.canvas.dimensions() == (4, 4)));
+    }
+
+    #[test]
+    fn dropped_frames_are_only_composed() {
+        let mut playback = playback();
+        let renderer = renderer();
+        let marker = Rgba([1, 2, 3, 4]);
+        let mut canvas = RgbaImage::from_pixel(2, 2, marker);
+
+        assert_eq!(playback.compose(0, &renderer, Some(&mut canvas)).unwrap(), Some(Duration::from_millis(100)));
+        assert_eq!(*canvas.get_pixel(0, 0), marker);
+
+        // Skipping the composed frame leaves a gap, so nothing is kept
+        // until the next pass.
+        playback.compose(1, &renderer, Some(&mut canvas)).unwrap();
+        assert_eq!(*canvas.get_pixel(0, 0), marker);
+        let (painted, _) = playback.frame(1, &renderer, Some(&mut canvas)).unwrap().unwrap();
+        assert_eq!(*painted.get_pixel(0, 0), Rgba([255, 255, 255, 255]));
+        assert!(playback.cache.is_empty());
+
+        playback.frame(0, &renderer, Some(&mut canvas)).unwrap();
+        assert_eq!(playback.cache.len(), 1);
+    }
+
+    #[test]
+    fn encoded_transitions_are_reused() {
+        let mut playback = playback();
+        let renderer = renderer();
+
+        for index in 0..3 {
+            playback.frame(index, &renderer, None).unwrap();
+        }
+        playback.set_encoded(1, false, "to white");
+        playback.set_encoded(2, false, "to black");
+        assert_eq!(playback.encoded(1, false), Some("to white"));
+        assert_eq!(playback.encoded(2, false), Some("to black"));
+        assert_eq!(playback.encoded(1, true), None);
+
+        // Frames that aren't kept don't keep their transitions either.
+        playback.set_encoded(5, false, "missing");
+        assert_eq!(playback.encoded(5, false), None);
+
+        // Switching to full width drops the transitions of the old width.
+        playback.set_encoded(2, true, "wide");
+        assert_eq!(playback.encoded(1, false), None);
+        assert_eq!(playback.encoded(1, true), None);
+        assert_eq!(playback.encoded(2, true), Some("wide"));
+    }
+}
@@ -0,0 +1,51 @@
+use std::{fmt::Display, str::FromStr};
+
+/// Characters used by the ASCII encoder, ordered from dark to bright.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Ramp(Vec<char>);
+
+impl Ramp {
+    pub const DEFAULT: &'static str = " .:-=+*#%@";
+
+    #[inline]
+    pub fn chars(&self) -> &[char] {
+        &self.0
+    }
+}
+
+impl Default for Ramp {
+    #[inline]
+    fn default() -> Self {
+        Self(Self::DEFAULT.chars().collect())
+    }
+}
+
+impl Display for Ramp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.iter().collect::<String>().fmt(f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RampParseError();
+
+impl Display for RampParseError {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        "illegal character ramp".fmt(f)
+    }
+}
+
+impl std::error::Error for RampParseError {}
+
+impl FromStr for Ramp {
+    type Err = RampParseError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = value.chars().collect();
+        if chars.is_empty() || chars.iter().any(|ch| ch.is_control()) {
+            return Err(RampParseError());
+        }
+        Ok(Ramp(chars))
+    }
+}
@@ -0,0 +1,525 @@
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use image::error::ImageResult;
+use image::{imageops, Frame, GenericImage, Pixel, RgbaImage};
+
+use crate::canvas_size::CanvasSize;
+use crate::cells::{cells_to_text_into, Cell};
+use crate::color::Color;
+use crate::color_depth::ColorDepth;
+use crate::encoder::{EncodeOptions, Encoder, EncoderState};
+use crate::size::aspect_corrected;
+use crate::ramp::Ramp;
+use crate::style::Style;
+use crate::dither::{dither_image, Dither};
+use crate::html::cells_to_html_into;
+use crate::image_to_ansi::move_cursor;
+use crate::term;
+
+/// How late [`Renderer::play`] may fall behind before it stops trying to
+/// catch up.
+const MAX_LAG: Duration = Duration::from_secs(1);
+
+/// How often [`Renderer::play`] checks the stop flag during long delays.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Renders images and animations to terminal escape sequences.
+///
+/// ```no_run
+/// use ansi_img::{canvas_size::CanvasSize, style::Style};
+/// use ansi_img::renderer::Renderer;
+///
+/// let image = image::open("image.png").unwrap().to_rgba8();
+/// let mut renderer = Renderer::new()
+///     .style(Style::Contain)
+///     .canvas_size(CanvasSize::Exact(80, 48));
+/// renderer.render(&image, &mut std::io::stdout()).unwrap();
+/// ```
+///
+/// The output of [`render`](Self::render) starts at the cursor position.
+/// The renderer remembers the last rendered frame, so rendering another
+/// image only draws the cells that changed, at the same position. It gets
+/// back there by moving the cursor up from the last row of the image, where
+/// the output leaves it, so nothing else may move the cursor in between.
+#[derive(Debug, Clone)]
+pub struct Renderer {
+    style: Style,
+    canvas_size: CanvasSize,
+    filter: imageops::FilterType,
+    background_color: Color,
+    encoder: Encoder,
+    cell_aspect: f64,
+    options: EncodeOptions,
+    prev_frame: Option<RgbaImage>,
+    state: EncoderState,
+    linebuf: String,
+}
+
+impl Default for Renderer {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            style: Style::ShrinkToFit,
+            canvas_size: CanvasSize::Window,
+            filter: imageops::FilterType::Nearest,
+            background_color: Color::Transparent,
+            encoder: Encoder::HalfBlock,
+            cell_aspect: term::DEFAULT_CELL_PIXEL_SIZE.0 as f64 / term::DEFAULT_CELL_PIXEL_SIZE.1 as f64,
+            options: EncodeOptions::default(),
+            prev_frame: None,
+            state: EncoderState::default(),
+            linebuf: String::new(),
+        }
+    }
+}
+
+impl Renderer {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Placement and scaling of the image on the canvas.
+    #[inline]
+    pub fn style(mut self, style: Style) -> Self {
+        self.style = style;
+        self
+    }
+
+    /// Size of the canvas in pixels, or the window or image size.
+    #[inline]
+    pub fn canvas_size(mut self, canvas_size: CanvasSize) -> Self {
+        self.canvas_size = canvas_size;
+        self
+    }
+
+    /// Filter used when resizing images.
+    #[inline]
+    pub fn filter(mut self, filter: imageops::FilterType) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    #[inline]
+    pub fn background_color(mut self, background_color: Color) -> Self {
+        self.background_color = background_color;
+        self
+    }
+
+    /// Pixels with a lower alpha value are transparent.
+    #[inline]
+    pub fn alpha_threshold(mut self, alpha_threshold: u8) -> Self {
+        self.options.alpha_threshold = alpha_threshold;
+        self
+    }
+
+    #[inline]
+    pub fn encoder(mut self, encoder: Encoder) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    /// Width divided by height of a character cell, see
+    /// [`detect_cell_aspect`](crate::term::detect_cell_aspect). Defaults
+    /// to 1:2.
+    #[inline]
+    pub fn cell_aspect(mut self, cell_aspect: f64) -> Self {
+        self.cell_aspect = cell_aspect;
+        self
+    }
+
+    /// Colors the terminal can display. `Auto` is treated as true color, use
+    /// [`detect_color_depth`](crate::term::detect_color_depth) to resolve
+    /// it first.
+    #[inline]
+    pub fn color_depth(mut self, color_depth: ColorDepth) -> Self {
+        self.options.color_depth = color_depth;
+        self
+    }
+
+    /// Dithering for limited color depths and braille. `Auto` uses
+    /// floyd-steinberg for still images and blue noise in
+    /// [`play`](Self::play).
+    #[inline]
+    pub fn dither(mut self, dither: Dither) -> Self {
+        self.options.dither = dither;
+        self
+    }
+
+    /// Luminance at which a braille dot is lit.
+    #[inline]
+    pub fn threshold(mut self, threshold: u8) -> Self {
+        self.options.threshold = threshold;
+        self
+    }
+
+    /// Characters of the ASCII encoders.
+    #[inline]
+    pub fn ramp(mut self, ramp: Ramp) -> Self {
+        self.options.ramp = ramp;
+        self
+    }
+
+    /// Compress image data of the kitty graphics protocol.
+    #[inline]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.options.compress = compress;
+        self
+    }
+
+    #[inline]
+    pub fn options(&self) -> &EncodeOptions {
+        &self.options
+    }
+
+    /// Width divided by height of a canvas pixel on screen. Graphics
+    /// protocols draw the canvas pixels as they are.
+    #[inline]
+    pub fn pixel_aspect(&self) -> f64 {
+        Self::pixel_aspect_of(self.encoder, self.cell_aspect)
+    }
+
+    #[inline]
+    fn pixel_aspect_of(encoder: Encoder, cell_aspect: f64) -> f64 {
+        if encoder.is_graphics() {
+            1.0
+        } else {
+            let (cell_width, cell_height) = encoder.cell_size();
+            cell_aspect * cell_height as f64 / cell_width as f64
+        }
+    }
+
+    /// Size of the terminal window in cells that is used for the canvas.
+    /// Graphics protocols scroll the screen when the image reaches the
+    /// bottom line, so that line is left free for them.
+    pub fn window_size(&self) -> Option<(usize, usize)> {
+        term::window_size().map(|(width, height)|
+            if self.encoder.is_graphics() { (width, height.saturating_sub(1).max(1)) } else { (width, height) })
+    }
+
+    /// Creates the canvas for an image of the given size, or `None` if the
+    /// image can be rendered as is.
+    pub fn new_canvas(&self, image_width: u32, image_height: u32) -> Option<RgbaImage> {
+        let (width, height) = match self.canvas_size {
+            CanvasSize::Exact(width, height) => (width, height),
+            CanvasSize::Window => {
+                let (columns, rows) = self.window_size()?;
+                let (cell_width, cell_height) = self.encoder.cell_size();
+                (columns as u32 * cell_width, rows as u32 * cell_height)
+            }
+            CanvasSize::Image =>
+                match self.style {
+                    Style::Position(x, y, size) => {
+                        let (w, h) = size.to_size(image_width, image_height, self.pixel_aspect());
+                        let x = x.unwrap_or(0);
+                        let y = y.unwrap_or(0);
+
+                        let w = (w as i64 + x as i64).max(0);
+                        let h = (h as i64 + y as i64).max(0);
+                        let w = if w > u32::MAX as i64 { u32::MAX } else { w as u32 };
+                        let h = if h > u32::MAX as i64 { u32::MAX } else { h as u32 };
+                        (w, h)
+                    },
+                    _ => {
+                        let (width, height) = aspect_corrected(image_width, image_height, self.pixel_aspect());
+                        if self.background_color == Color::Transparent && width == image_width && height == image_height {
+                            return None;
+                        }
+                        (width, height)
+                    }
+                },
+        };
+
+        let mut canvas = RgbaImage::new(width, height);
+        fill_color(&mut canvas, self.background_color);
+        Some(canvas)
+    }
+
+    /// Paints `image` onto `canvas` according to the style and reduces the
+    /// colors to the color depth if the encoder needs that.
+    pub fn paint(&self, image: &RgbaImage, canvas: &mut RgbaImage) {
+        self.paint_with(image, canvas, self.pixel_aspect());
+    }
+
+    #[inline]
+    fn paint_with(&self, image: &RgbaImage, canvas: &mut RgbaImage, pixel_aspect: f64) {
+        fill_color(canvas, self.background_color);
+        self.style.paint(image, canvas, self.filter, pixel_aspect);
+        self.quantize(canvas);
+    }
+
+    /// Reduces the colors of `image` to the color depth if the encoder
+    /// needs that.
+    pub fn quantize(&self, image: &mut RgbaImage) {
+        if self.encoder.quantizes_pixels() {
+            dither_image(image, self.options.color_depth, self.options.dither.resolve(false), self.options.alpha_threshold);
+        }
+    }
+
+    /// Encodes the already painted `image` into `lines`, only emitting what
+    /// differs from `prev_frame`. The renderer keeps what the encoder
+    /// carries over between frames, so call [`reset`](Self::reset) when
+    /// starting over with a blank `prev_frame`.
+    #[inline]
+    pub fn encode_into(&mut self, prev_frame: &RgbaImage, image: &RgbaImage, full_width: bool, lines: &mut String) {
+        self.encoder.encode_into(prev_frame, image, full_width, &self.options, &mut self.state, lines);
+    }
+
+    /// Encodes the already painted `image` as text for saving it to a file,
+    /// see [`cells_to_text_into`]. Graphics protocols can't be written as
+    /// text, so half blocks are used for them instead.
+    pub fn encode_text_into(&self, image: &RgbaImage, line_end: &str, lines: &mut String) {
+        let encoder = if self.encoder.is_graphics() { Encoder::HalfBlock } else { self.encoder };
+        encoder.with_cell_encoder(image, &self.options, |cells| {
+            cells_to_text_into(cells, image, self.options.alpha_threshold, self.options.color_depth, line_end, lines);
+        });
+    }
+
+    /// Like [`Renderer::encode_text_into`], but writes a `<pre>` element.
+    /// See [`cells_to_html_into`].
+    pub fn encode_html_into(&self, image: &RgbaImage, classes: bool, line_end: &str, html: &mut String) {
+        let encoder = if self.encoder.is_graphics() { Encoder::HalfBlock } else { self.encoder };
+        encoder.with_cell_encoder(image, &self.options, |cells| {
+            cells_to_html_into(cells, image, self.options.alpha_threshold, self.options.color_depth, classes, line_end, html);
+        });
+    }
+
+    /// Paints `image` onto a canvas of `columns` x `rows` character cells
+    /// and passes each encoded cell to `f` together with its column and row.
+    /// Graphics protocols can't be split into cells, so half blocks are used
+    /// for them instead.
+    pub fn render_cells(&self, image: &RgbaImage, columns: u32, rows: u32, mut f: impl FnMut(u32, u32, Cell)) {
+        let encoder = if self.encoder.is_graphics() { Encoder::HalfBlock } else { self.encoder };
+        let (cell_width, cell_height) = encoder.cell_size();
+        let mut canvas = RgbaImage::new(columns * cell_width, rows * cell_height);
+        self.paint_with(image, &mut canvas, Self::pixel_aspect_of(encoder, self.cell_aspect));
+
+        encoder.with_cell_encoder(&canvas, &self.options, |cells| {
+            for y in 0..rows {
+                for x in 0..columns {
+                    f(x, y, cells.encode_cell(&canvas, x, y, self.options.alpha_threshold).quantized(self.options.color_depth));
+                }
+            }
+        });
+    }
+
+    /// Renders `image` to `out`. See [`Renderer`] for where it ends up.
+    pub fn render<W: Write + ?Sized>(&mut self, image: &RgbaImage, out: &mut W) -> io::Result<()> {
+        let canvas = match self.new_canvas(image.width(), image.height()) {
+            Some(mut canvas) => {
+                self.paint(image, &mut canvas);
+                canvas
+            }
+            None => {
+                let mut canvas = image.clone();
+                self.quantize(&mut canvas);
+                canvas
+            }
+        };
+
+        let prev_frame = match self.prev_frame.take() {
+            Some(prev_frame) if prev_frame.dimensions() == canvas.dimensions() => {
+                let mut moves = String::new();
+                self.cursor_to_origin(&prev_frame, &mut moves);
+                out.write_all(moves.as_bytes())?;
+                prev_frame
+            }
+            _ => {
+                self.state = EncoderState::default();
+                RgbaImage::new(canvas.width(), canvas.height())
+            }
+        };
+
+        let mut linebuf = std::mem::take(&mut self.linebuf);
+        self.encode_into(&prev_frame, &canvas, self.canvas_size.is_window(), &mut linebuf);
+        let result = out.write_all(linebuf.as_bytes());
+
+        self.linebuf = linebuf;
+        self.prev_frame = Some(canvas);
+        result
+    }
+
+    /// Moves the cursor from where the encoder left it after `prev_frame`
+    /// back to the top-left corner of the image. Text encoders stop behind
+    /// the last column of the last row, graphics protocols at its first
+    /// column.
+    fn cursor_to_origin(&self, prev_frame: &RgbaImage, lines: &mut String) {
+        let (cell_width, cell_height) = self.encoder.cell_size();
+        let rows = prev_frame.height().div_ceil(cell_height);
+        if rows == 0 {
+            return;
+        }
+        // At the right screen edge the cursor stays on the last column, but
+        // moving left stops at the first one, which is where a canvas as
+        // wide as the window starts.
+        let column = if self.encoder.is_graphics() { 0 } else { prev_frame.width().div_ceil(cell_width) };
+        move_cursor(column, rows - 1, 0, 0, lines);
+    }
+
+    /// Like [`render`](Self::render), but returns the output.
+    pub fn render_to_string(&mut self, image: &RgbaImage) -> String {
+        let mut out = Vec::new();
+        let _ = self.render(image, &mut out);
+        String::from_utf8(out).unwrap_or_default()
+    }
+
+    /// Plays the animation `frames` of a `width` x `height` image on `out`,
+    /// showing each frame when the delays of the frames before it have
+    /// passed. `loop_count` of `None` loops forever. Returns early once
+    /// `stop` is set, e.g. by a Ctrl+C handler.
+    pub fn play<W: Write + ?Sized>(&mut self, width: u32, height: u32, frames: &[Frame], loop_count: Option<u32>, stop: &AtomicBool, out: &mut W) -> ImageResult<()> {
+        // Error diffusion would make the animation flicker.
+        let dither = self.options.dither;
+        self.options.dither = dither.resolve(true);
+        let result = self.play_frames(width, height, frames, loop_count, stop, out);
+        self.options.dither = dither;
+        result
+    }
+
+    fn play_frames<W: Write + ?Sized>(&mut self, width: u32, height: u32, frames: &[Frame], loop_count: Option<u32>, stop: &AtomicBool, out: &mut W) -> ImageResult<()> {
+        let mut frame_canvas = RgbaImage::new(width, height);
+        let mut deadline = Instant::now();
+        let mut loops = 0;
+        while loop_count.is_none_or(|loop_count| loops < loop_count) {
+            for frame in frames {
+                if stop.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                compose_frame(&mut frame_canvas, frame, self.background_color)?;
+                self.render(&frame_canvas, out)?;
+                out.flush()?;
+
+                // Time spent rendering counts towards the delay. Falling
+                // far behind starts over instead of catching up in a burst.
+                let now = Instant::now();
+                if now.saturating_duration_since(deadline) >= MAX_LAG {
+                    deadline = now;
+                }
+                deadline += Duration::from(frame.delay());
+                while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                    if stop.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    std::thread::sleep(left.min(STOP_POLL_INTERVAL));
+                }
+            }
+            loops += 1;
+        }
+        Ok(())
+    }
+
+    /// Forgets the last rendered frame, so that the next one is drawn
+    /// completely.
+    #[inline]
+    pub fn reset(&mut self) {
+        self.prev_frame = None;
+        self.state = EncoderState::default();
+    }
+}
+
+#[inline]
+pub fn fill_color(image: &mut RgbaImage, color: Color) {
+    match color {
+        Color::Transparent => {
+            image.fill(0);
+        }
+        Color::Solid(rgb) => {
+            let rgba = rgb.to_rgba();
+            for pixel in image.pixels_mut() {
+                *pixel = rgba;
+            }
+        }
+    }
+}
+
+/// Draws `frame` onto `frame_canvas`, which holds the previous frame.
+pub fn compose_frame(frame_canvas: &mut RgbaImage, frame: &Frame, background_color: Color) -> ImageResult<()> {
+    let frame_image = frame.buffer();
+    if let Color::Solid(rgb) = background_color {
+        let rgba = rgb.to_rgba();
+        for pixel in frame_canvas.pixels_mut() {
+            *pixel = rgba;
+        }
+        imageops::overlay(frame_canvas, frame_image, frame.left() as i64, frame.top() as i64);
+    } else {
+        if frame_image.width() != frame_canvas.width() ||
+           frame_image.height() != frame_canvas.height() ||
+           frame.left() != 0 || frame.top() != 0
+        {
+            frame_canvas.fill(0);
+        }
+        frame_canvas.copy_from(frame_image, frame.left(), frame.top())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Delay, Rgba};
+
+    use super::*;
+
+    fn renderer() -> Renderer {
+        Renderer::new()
+            .canvas_size(CanvasSize::Exact(2, 4))
+            .cell_aspect(0.5)
+            .color_depth(ColorDepth::TrueColor)
+    }
+
+    #[test]
+    fn next_frame_moves_back_to_the_image() {
+        let mut renderer = renderer();
+        let mut image = RgbaImage::from_pixel(2, 4, Rgba([255, 0, 0, 255]));
+
+        let first = renderer.render_to_string(&image);
+        assert!(!first.contains("\x1B[s"), "{first:?}");
+
+        image.put_pixel(1, 3, Rgba([0, 0, 255, 255]));
+        let second = renderer.render_to_string(&image);
+        assert!(second.starts_with("\x1B[2D\x1B[A"), "{second:?}");
+        assert!(second.ends_with("\x1B[38;2;0;0;255m▄\x1B[0m"), "{second:?}");
+
+        // Nothing changed, so the cursor only goes back and forth.
+        let third = renderer.render_to_string(&image);
+        assert_eq!(third, "\x1B[2D\x1B[A\x1B[0m\x1B[2C\x1B[B");
+    }
+
+    #[test]
+    fn graphics_move_back_from_the_first_column() {
+        let (_, cell_height) = Encoder::Sixel.cell_size();
+        let mut renderer = renderer()
+            .encoder(Encoder::Sixel)
+            .canvas_size(CanvasSize::Exact(2, cell_height * 2));
+        let image = RgbaImage::from_pixel(2, cell_height * 2, Rgba([255, 0, 0, 255]));
+
+        let first = renderer.render_to_string(&image);
+        assert!(first.ends_with("\x1B\\\x1B[B"), "{first:?}");
+        assert_eq!(renderer.render_to_string(&image), "\x1B[A\x1B[B");
+    }
+
+    #[test]
+    fn frames_wait_for_their_deadline() {
+        let frames: Vec<Frame> = [Rgba([255, 0, 0, 255]), Rgba([0, 0, 255, 255])].into_iter()
+            .map(|color| Frame::from_parts(RgbaImage::from_pixel(2, 4, color), 0, 0, Delay::from_numer_denom_ms(30, 1)))
+            .collect();
+        let mut renderer = renderer();
+        let mut out = Vec::new();
+
+        let start = Instant::now();
+        renderer.play(2, 4, &frames, Some(2), &AtomicBool::new(false), &mut out).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(120));
+        assert!(!out.is_empty());
+    }
+
+    #[test]
+    fn stop_flag_ends_playback() {
+        let frames = [Frame::from_parts(RgbaImage::new(2, 4), 0, 0, Delay::from_numer_denom_ms(1000, 1))];
+        let mut renderer = renderer();
+        let mut out = Vec::new();
+
+        renderer.play(2, 4, &frames, None, &AtomicBool::new(true), &mut out).unwrap();
+        assert!(out.is_empty());
+    }
+}
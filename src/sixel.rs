@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+use std::sync::Arc;
+
+use image::{imageops, Rgb, Rgba, RgbaImage};
+
+use crate::dither::{dither_to_palette, Dither};
+use crate::image_to_ansi::{cursor_to_last_row, move_cursor};
+use crate::palette::Palette;
+
+/// Number of color registers that practically all sixel terminals support.
+pub const MAX_COLORS: usize = 256;
+
+/// Builds a palette of at most `max_colors` colors for the opaque pixels of
+/// `image` using the median cut algorithm. Images that already have few
+/// enough colors get exactly their colors.
+pub fn median_cut(image: &RgbaImage, alpha_threshold: u8, max_colors: usize) -> Vec<Rgb<u8>> {
+    let mut histogram = HashMap::new();
+    for &Rgba([r, g, b, a]) in image.pixels() {
+        if a >= alpha_threshold {
+            *histogram.entry([r, g, b]).or_insert(0u32) += 1;
+        }
+    }
+
+    if histogram.len() <= max_colors {
+        let mut colors: Vec<_> = histogram.into_keys().map(Rgb).collect();
+        colors.sort_unstable_by_key(|&Rgb(rgb)| rgb);
+        return colors;
+    }
+
+    let mut entries: Vec<([u8; 3], u32)> = histogram.into_iter().collect();
+
+    // Boxes are ranges of `entries`, which get sorted within the box along
+    // the channel that is split.
+    let mut boxes: Vec<Range<usize>> = Vec::with_capacity(max_colors);
+    boxes.push(0..entries.len());
+
+    while boxes.len() < max_colors {
+        let mut best = None;
+        let mut best_score = 0u64;
+        for (index, range) in boxes.iter().enumerate() {
+            if range.len() < 2 {
+                continue;
+            }
+            let (channel, extent) = widest_channel(&entries[range.clone()]);
+            let count: u64 = entries[range.clone()].iter().map(|&(_, count)| count as u64).sum();
+            let score = extent as u64 * count;
+            if score > best_score {
+                best_score = score;
+                best = Some((index, channel));
+            }
+        }
+
+        let Some((index, channel)) = best else {
+            break;
+        };
+
+        let range = boxes[index].clone();
+        let slice = &mut entries[range.clone()];
+        slice.sort_unstable_by_key(|&(color, _)| color[channel]);
+
+        let total: u64 = slice.iter().map(|&(_, count)| count as u64).sum();
+        let mut sum = 0;
+        let mut split = 1;
+        for (offset, &(_, count)) in slice.iter().enumerate() {
+            sum += count as u64;
+            if sum * 2 >= total {
+                split = offset + 1;
+                break;
+            }
+        }
+        let split = range.start + split.clamp(1, slice.len() - 1);
+
+        boxes[index] = range.start..split;
+        boxes.push(split..range.end);
+    }
+
+    boxes.into_iter().map(|range| {
+        let mut sum = [0u64; 3];
+        let mut total = 0u64;
+        for &(color, count) in &entries[range] {
+            for channel in 0..3 {
+                sum[channel] += color[channel] as u64 * count as u64;
+            }
+            total += count as u64;
+        }
+        let half = total / 2;
+        Rgb([
+            ((sum[0] + half) / total) as u8,
+            ((sum[1] + half) / total) as u8,
+            ((sum[2] + half) / total) as u8,
+        ])
+    }).collect()
+}
+
+/// Channel with the biggest range of values and that range.
+fn widest_channel(entries: &[([u8; 3], u32)]) -> (usize, u8) {
+    let mut min = [u8::MAX; 3];
+    let mut max = [u8::MIN; 3];
+    for &(color, _) in entries {
+        for channel in 0..3 {
+            min[channel] = min[channel].min(color[channel]);
+            max[channel] = max[channel].max(color[channel]);
+        }
+    }
+    (0..3).map(|channel| (channel, max[channel] - min[channel]))
+        .max_by_key(|&(_, extent)| extent)
+        .unwrap_or((0, 0))
+}
+
+/// Renders `image` as a DEC sixel image into `lines`.
+///
+/// Only the character rows (of `cell_height` pixels) that differ from
+/// `prev_frame` are sent. The output starts at the cursor position of the
+/// top left corner of the image. Transparent pixels are not drawn (P2 = 1),
+/// so pixels that became transparent are erased by clearing their cells
+/// first.
+///
+/// Afterwards the cursor is moved to the first column of the last row, like
+/// [`cursor_to_last_row`] does for the other graphics protocols. That
+/// assumes the terminal leaves the cursor on the row of the image's bottom
+/// edge, at the column the image started, as xterm does with sixel
+/// scrolling enabled.
+///
+/// `last_palette` is the palette of the previous image. It is used again if
+/// it has all colors the image needs, which is common for the frames of an
+/// animation, and replaced otherwise.
+pub fn image_to_sixel_into(prev_frame: &RgbaImage, image: &RgbaImage, alpha_threshold: u8, dither: Dither, (cell_width, cell_height): (u32, u32), last_palette: &mut Option<Arc<Palette>>, lines: &mut String) {
+    lines.clear();
+
+    let width = image.width();
+    let height = image.height();
+
+    let changed_row = |y: u32| (0..width).any(|x| image.get_pixel(x, y) != prev_frame.get_pixel(x, y));
+    let Some(first) = (0..height).find(|&y| changed_row(y)) else {
+        cursor_to_last_row(height.div_ceil(cell_height), lines);
+        return;
+    };
+    let last = (first..height).rev().find(|&y| changed_row(y)).unwrap_or(first);
+
+    let first_row = first / cell_height;
+    let last_row = last / cell_height;
+    let y1 = first_row * cell_height;
+    let y2 = ((last_row + 1) * cell_height).min(height);
+
+    if first_row > 0 {
+        let _ = write!(lines, "\x1B[{first_row}B");
+    }
+
+    let cleared = (y1..y2).any(|y| (0..width).any(|x|
+        image.get_pixel(x, y)[3] < alpha_threshold && prev_frame.get_pixel(x, y)[3] >= alpha_threshold));
+
+    if cleared {
+        // CSI n X     Erase n characters (ECH), also erases graphics.
+        let columns = width.div_ceil(cell_width);
+        let rows = last_row - first_row + 1;
+        for row in 0..rows {
+            let _ = write!(lines, "\x1B[{columns}X");
+            if row + 1 < rows {
+                lines.push_str("\x1B[1B");
+            }
+        }
+        if rows > 1 {
+            let _ = write!(lines, "\x1B[{}A", rows - 1);
+        }
+    }
+
+    let mut band = imageops::crop_imm(image, 0, y1, width, y2 - y1).to_image();
+    let colors = median_cut(&band, alpha_threshold, MAX_COLORS);
+    if !colors.is_empty() {
+        let palette = palette_for(colors, last_palette);
+        dither_to_palette(&mut band, &palette, dither, alpha_threshold);
+
+        write_sixel(&band, &palette, alpha_threshold, lines);
+    }
+
+    move_cursor(0, first_row, 0, height.div_ceil(cell_height) - 1, lines);
+}
+
+/// A palette with `colors`. The last palette keeps the nearest colors it
+/// already looked up and the same colors keep the same registers, so it is
+/// used again if it has all of `colors`.
+fn palette_for(colors: Vec<Rgb<u8>>, last_palette: &mut Option<Arc<Palette>>) -> Arc<Palette> {
+    if let Some(palette) = last_palette {
+        if colors.iter().all(|&color| palette.contains(color)) {
+            return palette.clone();
+        }
+    }
+
+    let palette = Arc::new(Palette::new(colors, 0));
+    *last_palette = Some(palette.clone());
+    palette
+}
+
+/// Writes the DCS sequence for `image`, whose colors are already quantized
+/// to `palette`.
+fn write_sixel(image: &RgbaImage, palette: &Palette, alpha_threshold: u8, lines: &mut String) {
+    let width = image.width();
+    let height = image.height();
+
+    // DCS P1 ; P2 ; P3 q     P2 = 1: pixels without color stay unchanged.
+    // " Pan ; Pad ; Ph ; Pv  Raster attributes, 1:1 pixel aspect ratio.
+    let _ = write!(lines, "\x1BP0;1;0q\"1;1;{width};{height}");
+
+    for (index, &Rgb([r, g, b])) in palette.colors().iter().enumerate() {
+        let r = (r as u32 * 100 + 127) / 255;
+        let g = (g as u32 * 100 + 127) / 255;
+        let b = (b as u32 * 100 + 127) / 255;
+        let _ = write!(lines, "#{index};2;{r};{g};{b}");
+    }
+
+    let mut bits: Vec<Vec<u8>> = vec![Vec::new(); palette.colors().len()];
+    let mut used = Vec::new();
+
+    for band_y in (0..height).step_by(6) {
+        for (dy, y) in (band_y..(band_y + 6).min(height)).enumerate() {
+            for x in 0..width {
+                let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+                if a < alpha_threshold {
+                    continue;
+                }
+                let index = palette.nearest(Rgb([r, g, b]));
+                let row = &mut bits[index];
+                if row.is_empty() {
+                    row.resize(width as usize, 0);
+                    used.push(index);
+                }
+                row[x as usize] |= 1 << dy;
+            }
+        }
+
+        used.sort_unstable();
+        for (position, &index) in used.iter().enumerate() {
+            if position > 0 {
+                // Graphics carriage return.
+                lines.push('$');
+            }
+            let _ = write!(lines, "#{index}");
+            write_runs(&bits[index], lines);
+            bits[index].clear();
+        }
+        used.clear();
+
+        // Graphics new line.
+        lines.push('-');
+    }
+
+    lines.push_str("\x1B\\");
+}
+
+/// Writes sixel characters for `row` with run-length compression, leaving
+/// out trailing empty sixels.
+fn write_runs(row: &[u8], lines: &mut String) {
+    let end = row.iter().rposition(|&sixel| sixel != 0).map_or(0, |index| index + 1);
+    let mut index = 0;
+    while index < end {
+        let sixel = row[index];
+        let mut run = 1;
+        while index + run < end && row[index + run] == sixel {
+            run += 1;
+        }
+        let ch = (0x3F + sixel) as char;
+        if run > 3 {
+            let _ = write!(lines, "!{run}{ch}");
+        } else {
+            for _ in 0..run {
+                lines.push(ch);
+            }
+        }
+        index += run;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palette_is_kept_while_it_fits() {
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let first = RgbaImage::from_fn(4, 4, |x, _| if x < 2 { red } else { blue });
+        let second = RgbaImage::from_pixel(4, 4, red);
+        let third = RgbaImage::from_pixel(4, 4, Rgba([0, 255, 0, 255]));
+        let blank = RgbaImage::new(4, 4);
+
+        let mut last_palette = None;
+        let mut lines = String::new();
+        image_to_sixel_into(&blank, &first, 127, Dither::None, (4, 4), &mut last_palette, &mut lines);
+        let palette = last_palette.clone().unwrap();
+        assert_eq!(palette.colors(), [Rgb([0, 0, 255]), Rgb([255, 0, 0])]);
+
+        image_to_sixel_into(&first, &second, 127, Dither::None, (4, 4), &mut last_palette, &mut lines);
+        assert!(Arc::ptr_eq(&palette, last_palette.as_ref().unwrap()));
+        assert!(lines.starts_with("\x1BP0;1;0q\"1;1;4;4#0;2;0;0;100#1;2;100;0;0"), "{lines:?}");
+
+        image_to_sixel_into(&second, &third, 127, Dither::None, (4, 4), &mut last_palette, &mut lines);
+        assert_eq!(last_palette.unwrap().colors(), [Rgb([0, 255, 0])]);
+    }
+}
@@ -0,0 +1,98 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Size {
+    Scale (i32),
+    Width (u32),
+    Height (u32),
+    Exact (u32, u32),
+}
+
+/// Size in canvas pixels at which an image of `width` x `height` square
+/// pixels keeps its aspect ratio on a canvas whose pixels are
+/// `pixel_aspect` times as wide as they are high. The image is only ever
+/// stretched, so no detail is lost.
+pub fn aspect_corrected(width: u32, height: u32, pixel_aspect: f64) -> (u32, u32) {
+    if pixel_aspect == 1.0 || width == 0 || height == 0 {
+        (width, height)
+    } else if pixel_aspect < 1.0 {
+        (((width as f64 / pixel_aspect).round() as u32).max(1), height)
+    } else {
+        (width, ((height as f64 * pixel_aspect).round() as u32).max(1))
+    }
+}
+
+impl Size {
+    /// Size of the scaled image in canvas pixels, which are `pixel_aspect`
+    /// times as wide as they are high. A width or height alone keeps the
+    /// aspect ratio of the image on screen. Zoom values and exact sizes are
+    /// taken as canvas pixels as they are, so that zooming by 1 doesn't
+    /// resample the image.
+    pub fn to_size(&self, image_width: u32, image_height: u32, pixel_aspect: f64) -> (u32, u32) {
+        match *self {
+            Self::Scale(z) => {
+                let width;
+                let height;
+                if z > 1 {
+                    if image_width > image_height {
+                        if image_width > (u32::MAX / z as u32) {
+                            width  = u32::MAX;
+                            height = (u32::MAX as u64 * image_height as u64 / image_width as u64) as u32;
+                        } else {
+                            width  = image_width  * z as u32;
+                            height = image_height * z as u32;
+                        }
+                    } else {
+                        if image_height > (u32::MAX / z as u32) {
+                            width  = (u32::MAX as u64 * image_width as u64 / image_height as u64) as u32;
+                            height = u32::MAX;
+                        } else {
+                            width  = image_width  * z as u32;
+                            height = image_height * z as u32;
+                        }
+                    }
+                } else if z == 1 {
+                    width  = image_width;
+                    height = image_height;
+                } else {
+                    width  = image_width  / (-z as u32);
+                    height = image_height / (-z as u32);
+                }
+
+                (width, height)
+            },
+            Self::Width(w) => {
+                (w, (w as f64 * pixel_aspect * image_height as f64 / image_width as f64) as u32)
+            },
+            Self::Height(h) => {
+                ((h as f64 * image_width as f64 / (image_height as f64 * pixel_aspect)) as u32, h)
+            },
+            Self::Exact(w, h) => (w, h),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrected_by_stretching() {
+        assert_eq!(aspect_corrected(10, 10, 1.0), (10, 10));
+        assert_eq!(aspect_corrected(10, 10, 0.5), (20, 10));
+        assert_eq!(aspect_corrected(10, 10, 2.0), (10, 20));
+        assert_eq!(aspect_corrected(0, 10, 0.5), (0, 10));
+    }
+
+    #[test]
+    fn zoom_and_exact_sizes_are_not_corrected() {
+        assert_eq!(Size::Scale(1).to_size(10, 6, 0.9), (10, 6));
+        assert_eq!(Size::Scale(2).to_size(10, 6, 0.9), (20, 12));
+        assert_eq!(Size::Scale(-2).to_size(10, 6, 0.9), (5, 3));
+        assert_eq!(Size::Exact(7, 3).to_size(10, 6, 0.9), (7, 3));
+    }
+
+    #[test]
+    fn width_or_height_keep_the_aspect_ratio() {
+        assert_eq!(Size::Width(20).to_size(10, 10, 0.5), (20, 10));
+        assert_eq!(Size::Height(10).to_size(10, 10, 0.5), (20, 10));
+    }
+}
@@ -1,8 +1,8 @@
-use std::{fmt::Display, str::FromStr};
+use std::{borrow::Cow, fmt::Display, str::FromStr};
 
 use image::{imageops, RgbaImage};
 
-use super::size::Size;
+use crate::size::{aspect_corrected, Size};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Style {
@@ -14,18 +14,31 @@ pub enum Style {
     ShrinkToFit,
 }
 
+/// `image` stretched so that it keeps its aspect ratio on a canvas whose
+/// pixels are `pixel_aspect` times as wide as they are high.
 #[inline]
-fn draw_contain(image: &RgbaImage, canvas: &mut RgbaImage, filter: imageops::FilterType) {
-    if canvas.width() == image.width() && canvas.height() == image.height() {
+fn aspect_corrected_image(image: &RgbaImage, filter: imageops::FilterType, pixel_aspect: f64) -> Cow<'_, RgbaImage> {
+    let (width, height) = aspect_corrected(image.width(), image.height(), pixel_aspect);
+    if width == image.width() && height == image.height() {
+        Cow::Borrowed(image)
+    } else {
+        Cow::Owned(imageops::resize(image, width, height, filter))
+    }
+}
+
+#[inline]
+fn draw_contain(image: &RgbaImage, canvas: &mut RgbaImage, filter: imageops::FilterType, pixel_aspect: f64) {
+    let (image_width, image_height) = aspect_corrected(image.width(), image.height(), pixel_aspect);
+    if canvas.dimensions() == image.dimensions() && (image_width, image_height) == image.dimensions() {
         imageops::overlay(canvas, image, 0, 0);
     } else {
         let mut width = canvas.width();
-        let mut height = image.height() * width / image.width();
+        let mut height = image_height * width / image_width;
         let x;
         let y;
         if height > canvas.height() {
             height = canvas.height();
-            width = image.width() * height / image.height();
+            width = image_width * height / image_height;
             x = (canvas.width() as i64 - width as i64) / 2;
             y = 0;
         } else {
@@ -45,22 +58,26 @@ fn draw_center(image: &RgbaImage, canvas: &mut RgbaImage) {
 }
 
 impl Style {
-    pub fn paint(&self, image: &RgbaImage, canvas: &mut RgbaImage, filter: imageops::FilterType) {
+    /// Paints `image` onto `canvas`, whose pixels are `pixel_aspect` times
+    /// as wide as they are high. The image is scaled non-uniformly so that
+    /// it keeps its aspect ratio on screen.
+    pub fn paint(&self, image: &RgbaImage, canvas: &mut RgbaImage, filter: imageops::FilterType, pixel_aspect: f64) {
         match *self {
             Style::Center => {
-                draw_center(image, canvas);
+                draw_center(&aspect_corrected_image(image, filter, pixel_aspect), canvas);
             },
             Style::Tile => {
+                let image = aspect_corrected_image(image, filter, pixel_aspect);
                 for y in (0..canvas.height()).step_by(image.height() as usize) {
                     for x in (0..canvas.width()).step_by(image.width() as usize) {
-                        imageops::overlay(canvas, image, x.into(), y.into());
+                        imageops::overlay(canvas, image.as_ref(), x.into(), y.into());
                     }
                 }
             },
             Style::Position(x, y, size) => {
                 let image_width  = image.width();
                 let image_height = image.height();
-                let (w, h) = size.to_size(image_width, image_height);
+                let (w, h) = size.to_size(image_width, image_height, pixel_aspect);
 
                 let x = if let Some(x) = x {
                     x.into()
@@ -84,16 +101,17 @@ impl Style {
                 }
             },
             Style::Cover => {
-                if canvas.width() == image.width() && canvas.height() == image.height() {
+                let (image_width, image_height) = aspect_corrected(image.width(), image.height(), pixel_aspect);
+                if canvas.dimensions() == image.dimensions() && (image_width, image_height) == image.dimensions() {
                     imageops::overlay(canvas, image, 0, 0);
                 } else {
                     let mut width = canvas.width();
-                    let mut height = image.height() * width / image.width();
+                    let mut height = image_height * width / image_width;
                     let x;
                     let y;
                     if height < canvas.height() {
                         height = canvas.height();
-                        width = image.width() * height / image.height();
+                        width = image_width * height / image_height;
                         x = (canvas.width() as i64 - width as i64) / 2;
                         y = 0;
                     } else {
@@ -105,13 +123,14 @@ impl Style {
                 }
             },
             Style::Contain => {
-                draw_contain(image, canvas, filter);
+                draw_contain(image, canvas, filter, pixel_aspect);
             },
             Style::ShrinkToFit => {
-                if image.width() <= canvas.width() && image.height() <= canvas.height() {
-                    draw_center(image, canvas);
+                let (image_width, image_height) = aspect_corrected(image.width(), image.height(), pixel_aspect);
+                if image_width <= canvas.width() && image_height <= canvas.height() {
+                    draw_center(&aspect_corrected_image(image, filter, pixel_aspect), canvas);
                 } else {
-                    draw_contain(image, canvas, filter);
+                    draw_contain(image, canvas, filter, pixel_aspect);
                 }
             },
         }
@@ -197,28 +216,28 @@ fn parse_position_rest(x: Option<i32>, mut tokenizer: StyleTokenizer) -> Result<
     match (token1, token2) {
         (StyleToken::Asterisk, StyleToken::Asterisk) => {
             tokenizer.expect_end()?;
-            return Ok(Style::Position(x, y, Size::Scale(1)));
+            Ok(Style::Position(x, y, Size::Scale(1)))
         }
         (StyleToken::Asterisk, StyleToken::Int(h)) => {
             if h < 0 {
                 return Err(StyleParseError());
             }
             tokenizer.expect_end()?;
-            return Ok(Style::Position(x, y, Size::Height(h as u32)));
+            Ok(Style::Position(x, y, Size::Height(h as u32)))
         }
         (StyleToken::Int(w), StyleToken::Asterisk) => {
             if w < 0 {
                 return Err(StyleParseError());
             }
             tokenizer.expect_end()?;
-            return Ok(Style::Position(x, y, Size::Width(w as u32)));
+            Ok(Style::Position(x, y, Size::Width(w as u32)))
         }
         (StyleToken::Int(w), StyleToken::Int(h)) => {
             if w < 0 || h < 0 {
                 return Err(StyleParseError());
             }
             tokenizer.expect_end()?;
-            return Ok(Style::Position(x, y, Size::Exact(w as u32, h as u32)));
+            Ok(Style::Position(x, y, Size::Exact(w as u32, h as u32)))
         }
         (StyleToken::Int(1), StyleToken::Slash) => {
             let divisor = tokenizer.expect_int()?;
@@ -229,10 +248,10 @@ fn parse_position_rest(x: Option<i32>, mut tokenizer: StyleTokenizer) -> Result<
 
             tokenizer.expect_end()?;
 
-            return Ok(Style::Position(x, y, Size::Scale(-divisor)));
+            Ok(Style::Position(x, y, Size::Scale(-divisor)))
         }
         _ => {
-            return Err(StyleParseError());
+            Err(StyleParseError())
         }
     }
 }
@@ -251,35 +270,35 @@ impl FromStr for Style {
         match token {
             StyleToken::Center => {
                 tokenizer.expect_end()?;
-                return Ok(Style::Center);
+                Ok(Style::Center)
             }
             StyleToken::Contain => {
                 tokenizer.expect_end()?;
-                return Ok(Style::Contain);
+                Ok(Style::Contain)
             }
             StyleToken::Cover => {
                 tokenizer.expect_end()?;
-                return Ok(Style::Cover);
+                Ok(Style::Cover)
             }
             StyleToken::Tile => {
                 tokenizer.expect_end()?;
-                return Ok(Style::Tile);
+                Ok(Style::Tile)
             }
             StyleToken::ShrinkToFit => {
                 tokenizer.expect_end()?;
-                return Ok(Style::ShrinkToFit);
+                Ok(Style::ShrinkToFit)
             }
             StyleToken::Position => {
                 let x = tokenizer.expect_int_or_asterisk()?;
-                return parse_position_rest(x, tokenizer);
+                parse_position_rest(x, tokenizer)
             },
             StyleToken::Int(x) => {
-                return parse_position_rest(Some(x), tokenizer);
+                parse_position_rest(Some(x), tokenizer)
             },
             StyleToken::Asterisk => {
-                return parse_position_rest(None, tokenizer);
+                parse_position_rest(None, tokenizer)
             },
-            _ => return Err(StyleParseError())
+            _ => Err(StyleParseError())
         }
     }
 }
@@ -310,8 +329,8 @@ impl<'a> StyleTokenizer<'a> {
 
         let token = token?;
         match token {
-            StyleToken::Int(value) => return Ok(value),
-            _ => return Err(StyleParseError()),
+            StyleToken::Int(value) => Ok(value),
+            _ => Err(StyleParseError()),
         }
     }
 
@@ -322,9 +341,9 @@ impl<'a> StyleTokenizer<'a> {
 
         let token = token?;
         match token {
-            StyleToken::Asterisk => return Ok(None),
-            StyleToken::Int(value) => return Ok(Some(value)),
-            _ => return Err(StyleParseError()),
+            StyleToken::Asterisk => Ok(None),
+            StyleToken::Int(value) => Ok(Some(value)),
+            _ => Err(StyleParseError()),
         }
     }
 }
@@ -336,9 +355,7 @@ impl<'a> Iterator for StyleTokenizer<'a> {
         if self.err {
             return Some(Err(StyleParseError()));
         }
-        let Some(index) = self.src.find(|ch: char| !ch.is_whitespace()) else {
-            return None;
-        };
+        let index = self.src.find(|ch: char| !ch.is_whitespace())?;
         self.src = &self.src[index..];
         if self.src.starts_with('/') {
             self.src = &self.src[1..];
@@ -375,7 +392,7 @@ impl<'a> Iterator for StyleTokenizer<'a> {
             return Some(Ok(token));
         }
 
-        if !self.src.starts_with(|ch: char| ch == '-' || ch == '+' || (ch >= '0' && ch <= '9')) {
+        if !self.src.starts_with(|ch: char| ch == '-' || ch == '+' || ch.is_ascii_digit()) {
             self.err = true;
             return Some(Err(StyleParseError()));
         }
@@ -385,7 +402,7 @@ impl<'a> Iterator for StyleTokenizer<'a> {
             self.src = &self.src[1..];
             sign = 1;
 
-            if !self.src.starts_with(|ch: char| ch >= '0' && ch <= '9') {
+            if !self.src.starts_with(|ch: char| ch.is_ascii_digit()) {
                 self.err = true;
                 return Some(Err(StyleParseError()));
             }
@@ -393,7 +410,7 @@ impl<'a> Iterator for StyleTokenizer<'a> {
             self.src = &self.src[1..];
             sign = -1;
 
-            if !self.src.starts_with(|ch: char| ch >= '0' && ch <= '9') {
+            if !self.src.starts_with(|ch: char| ch.is_ascii_digit()) {
                 self.err = true;
                 return Some(Err(StyleParseError()));
             }
@@ -402,12 +419,8 @@ impl<'a> Iterator for StyleTokenizer<'a> {
         }
 
         let mut value: i32 = 0;
-        loop {
-            let Some(ch) = self.src.chars().next() else {
-                break;
-            };
-
-            if !(ch >= '0' && ch <= '9') {
+        while let Some(ch) = self.src.chars().next() {
+            if !ch.is_ascii_digit() {
                 break;
             }
 
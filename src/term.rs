@@ -0,0 +1,513 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, Once, PoisonError};
+use std::time::Duration;
+
+use crate::color_depth::ColorDepth;
+use crate::terminfo::Terminfo;
+
+/// How long to wait for the terminal to answer a query.
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Cell size in pixels assumed when the terminal doesn't report it.
+pub const DEFAULT_CELL_PIXEL_SIZE: (u32, u32) = (10, 20);
+
+#[cfg(target_family = "unix")]
+#[inline]
+pub fn get_termios(fd: libc::c_int) -> Option<libc::termios> {
+    let mut term = std::mem::MaybeUninit::<libc::termios>::zeroed();
+    if unsafe { libc::tcgetattr(fd, term.as_mut_ptr()) } == 0 {
+        Some(unsafe { term.assume_init() })
+    } else {
+        None
+    }
+}
+
+#[cfg(target_family = "unix")]
+#[inline]
+pub fn set_termios(fd: libc::c_int, term: &libc::termios) -> bool {
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, term) == 0 }
+}
+
+/// What [`restore`] has to undo.
+struct Restore {
+    line_end: &'static str,
+    alt_screen: bool,
+    #[cfg(target_family = "unix")]
+    termios: Option<(libc::c_int, libc::termios)>,
+}
+
+static RESTORE: Mutex<Option<Restore>> = Mutex::new(None);
+
+#[inline]
+fn lock_restore() -> MutexGuard<'static, Option<Restore>> {
+    RESTORE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Hides the cursor and turns off auto-wrap for as long as it exists.
+/// When it is dropped, or on a panic, the terminal is put back the way it
+/// was, see [`restore`].
+pub struct TerminalGuard(());
+
+impl TerminalGuard {
+    /// Takes over the terminal. `line_end` is printed after the output
+    /// when restoring it, unless the alternate screen was used.
+    pub fn new(line_end: &'static str) -> Self {
+        *lock_restore() = Some(Restore {
+            line_end,
+            alt_screen: false,
+            // The terminal modes are remembered before anything changes them.
+            #[cfg(target_family = "unix")]
+            termios: [libc::STDIN_FILENO, libc::STDOUT_FILENO].into_iter()
+                .find_map(|fd| Some((fd, get_termios(fd)?))),
+        });
+
+        static PANIC_HOOK: Once = Once::new();
+        PANIC_HOOK.call_once(|| {
+            let default_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                // Otherwise the message would end up on the alternate screen.
+                restore();
+                default_hook(info);
+            }));
+        });
+
+        // CSI ?  7 l     No Auto-Wrap Mode (DECAWM), VT100.
+        // CSI ? 25 l     Hide cursor (DECTCEM), VT220
+        let _ = std::io::stdout().write_all(b"\x1B[?25l\x1B[?7l");
+        Self(())
+    }
+}
+
+impl Drop for TerminalGuard {
+    #[inline]
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Switches to the alternate screen buffer, if a [`TerminalGuard`] exists
+/// that switches back.
+pub fn enter_alt_screen() {
+    if let Some(restore) = &mut *lock_restore() {
+        if !restore.alt_screen {
+            // CSI ? 1049 h     Save cursor and switch to the alternate screen.
+            let _ = std::io::stdout().write_all(b"\x1B[?1049h");
+            restore.alt_screen = true;
+        }
+    }
+}
+
+/// Whether output currently goes to the alternate screen buffer.
+#[inline]
+pub fn is_alt_screen() -> bool {
+    lock_restore().as_ref().is_some_and(|restore| restore.alt_screen)
+}
+
+/// Undoes what the [`TerminalGuard`] changed: resets the attributes, shows
+/// the cursor, turns auto-wrap back on, leaves the alternate screen and
+/// restores the terminal modes. Does nothing if it was already restored.
+pub fn restore() {
+    let Some(restore) = lock_restore().take() else {
+        return;
+    };
+
+    let mut out = std::io::stdout().lock();
+    // CSI 0 m        Reset or normal, all attributes become turned off
+    // CSI ?  7 h     Auto-Wrap Mode (DECAWM), VT100
+    // CSI ? 25 h     Show cursor (DECTCEM), VT220
+    let _ = out.write_all(b"\x1B[0m\x1B[?25h\x1B[?7h");
+    if restore.alt_screen {
+        // CSI ? 1049 l     Switch back to the normal screen and restore the cursor.
+        let _ = out.write_all(b"\x1B[?1049l");
+    } else {
+        let _ = out.write_all(restore.line_end.as_bytes());
+    }
+    let _ = out.flush();
+
+    #[cfg(target_family = "unix")]
+    if let Some((fd, termios)) = &restore.termios {
+        set_termios(*fd, termios);
+    }
+}
+
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+/// Starts noticing when the terminal window is resized (SIGWINCH), see
+/// [`take_resized`]. Waiting in `poll` or `nanosleep` is interrupted by
+/// it. Returns `false` if the signal handler couldn't be installed.
+#[cfg(target_family = "unix")]
+pub fn watch_resize() -> bool {
+    extern "C" fn on_resize(_signal: libc::c_int) {
+        RESIZED.store(true, Ordering::Relaxed);
+    }
+
+    let mut action = unsafe { std::mem::zeroed::<libc::sigaction>() };
+    action.sa_sigaction = on_resize as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    // Other system calls, e.g. of the decoder thread, just carry on.
+    action.sa_flags = libc::SA_RESTART;
+    unsafe {
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut()) == 0
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+#[inline]
+pub fn watch_resize() -> bool {
+    false
+}
+
+/// Whether the window was resized since the last [`take_resized`].
+#[inline]
+pub fn resized() -> bool {
+    RESIZED.load(Ordering::Relaxed)
+}
+
+/// Whether the window was resized since the last call, resetting it.
+#[inline]
+pub fn take_resized() -> bool {
+    RESIZED.swap(false, Ordering::Relaxed)
+}
+
+/// Size of the terminal window in columns and rows, as reported by the
+/// kernel for the first of stdout, stdin and stderr that is a terminal.
+#[cfg(target_family = "unix")]
+pub fn window_size() -> Option<(usize, usize)> {
+    [libc::STDOUT_FILENO, libc::STDIN_FILENO, libc::STDERR_FILENO].into_iter().find_map(|fd| {
+        let mut size = std::mem::MaybeUninit::<libc::winsize>::zeroed();
+        if unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, size.as_mut_ptr()) } != 0 {
+            return None;
+        }
+        let size = unsafe { size.assume_init() };
+        if size.ws_col == 0 || size.ws_row == 0 {
+            return None;
+        }
+        Some((size.ws_col as usize, size.ws_row as usize))
+    })
+}
+
+#[cfg(not(target_family = "unix"))]
+#[inline]
+pub fn window_size() -> Option<(usize, usize)> {
+    None
+}
+
+/// Width and height in pixels of one character cell, as reported by the
+/// kernel for the terminal connected to stdout. Many terminals leave the
+/// pixel size at 0, in which case this returns `None`.
+#[cfg(target_family = "unix")]
+pub fn cell_pixel_size() -> Option<(u32, u32)> {
+    let mut size = std::mem::MaybeUninit::<libc::winsize>::zeroed();
+    if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, size.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let size = unsafe { size.assume_init() };
+    if size.ws_col == 0 || size.ws_row == 0 || size.ws_xpixel == 0 || size.ws_ypixel == 0 {
+        return None;
+    }
+    Some((
+        (size.ws_xpixel / size.ws_col) as u32,
+        (size.ws_ypixel / size.ws_row) as u32,
+    ))
+}
+
+#[cfg(not(target_family = "unix"))]
+#[inline]
+pub fn cell_pixel_size() -> Option<(u32, u32)> {
+    None
+}
+
+/// Width divided by height of a character cell. Uses the pixel size the
+/// kernel reports, or asks the terminal with `CSI 16 t`. Terminals that
+/// don't answer within `query_timeout` get the ratio of
+/// [`DEFAULT_CELL_PIXEL_SIZE`].
+pub fn detect_cell_aspect(query_timeout: Duration) -> f64 {
+    let (width, height) = cell_pixel_size()
+        .or_else(|| query_cell_pixel_size(query_timeout))
+        .unwrap_or(DEFAULT_CELL_PIXEL_SIZE);
+    width as f64 / height as f64
+}
+
+/// Asks the terminal for the size of a character cell in pixels using
+/// `CSI 16 t`, which is answered with `CSI 6 ; height ; width t`. Like
+/// [`query_color_depth`] a DA1 query is sent after it to know when not to
+/// wait any longer.
+#[cfg(target_family = "unix")]
+pub fn query_cell_pixel_size(timeout: Duration) -> Option<(u32, u32)> {
+    let response = query_tty("\x1B[16t\x1B[c", timeout, |response| {
+        find_da1_response(response).is_some()
+    })?;
+
+    let da1 = find_da1_response(&response)?;
+    let start = find(&response[..da1], b"\x1B[6;")? + 4;
+    let end = start + response[start..da1].iter().position(|&byte| byte == b't')?;
+    let params = std::str::from_utf8(&response[start..end]).ok()?;
+    let (height, width) = params.split_once(';')?;
+    let width:  u32 = width.parse().ok()?;
+    let height: u32 = height.parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    Some((width, height))
+}
+
+#[cfg(not(target_family = "unix"))]
+#[inline]
+pub fn query_cell_pixel_size(_timeout: Duration) -> Option<(u32, u32)> {
+    None
+}
+
+/// Figures out the best color depth the terminal supports.
+///
+/// Looks at `COLORTERM`, `TERM` and the terminfo entry of `TERM`. If that
+/// doesn't already prove true color support the terminal itself is asked
+/// via XTGETTCAP, and its answer wins. Terminals that don't answer within
+/// `query_timeout` get what the environment says.
+pub fn detect_color_depth(query_timeout: Duration) -> ColorDepth {
+    let color_depth = color_depth_from_env();
+    if color_depth == Some(ColorDepth::TrueColor) {
+        return ColorDepth::TrueColor;
+    }
+
+    if color_depth != Some(ColorDepth::Monochrome) {
+        if let Some(color_depth) = query_color_depth(query_timeout) {
+            return color_depth;
+        }
+    }
+
+    color_depth.unwrap_or(ColorDepth::Ansi8)
+}
+
+/// Color depth as far as it can be told by environment variables and the
+/// terminfo database.
+pub fn color_depth_from_env() -> Option<ColorDepth> {
+    if let Some(colorterm) = std::env::var_os("COLORTERM") {
+        if colorterm.eq_ignore_ascii_case("truecolor") || colorterm.eq_ignore_ascii_case("24bit") {
+            return Some(ColorDepth::TrueColor);
+        }
+    }
+
+    let term = std::env::var("TERM").ok()?;
+    if term.is_empty() {
+        return None;
+    }
+
+    if term == "dumb" {
+        return Some(ColorDepth::Monochrome);
+    }
+
+    if let Some(terminfo) = Terminfo::load(&term) {
+        if terminfo.has_true_color() {
+            return Some(ColorDepth::TrueColor);
+        }
+
+        if let Some(colors) = terminfo.max_colors() {
+            return Some(color_depth_from_count(colors));
+        }
+    }
+
+    if term.ends_with("-direct") || term.contains("truecolor") || term.contains("24bit") {
+        Some(ColorDepth::TrueColor)
+    } else if term.ends_with("256color") {
+        Some(ColorDepth::Xterm256)
+    } else if term.ends_with("16color") || term == "linux" {
+        Some(ColorDepth::Ansi16)
+    } else if term.ends_with("-mono") || term.ends_with("-m") {
+        Some(ColorDepth::Monochrome)
+    } else if term.contains("color") || term.starts_with("xterm") || term.starts_with("screen") || term.starts_with("tmux") {
+        Some(ColorDepth::Ansi8)
+    } else {
+        None
+    }
+}
+
+#[inline]
+fn color_depth_from_count(colors: i32) -> ColorDepth {
+    if colors >= 0x1000000 {
+        ColorDepth::TrueColor
+    } else if colors >= 256 {
+        ColorDepth::Xterm256
+    } else if colors >= 16 {
+        ColorDepth::Ansi16
+    } else if colors >= 8 {
+        ColorDepth::Ansi8
+    } else {
+        ColorDepth::Monochrome
+    }
+}
+
+/// Asks the terminal for its `RGB`, `Tc` and `colors` capabilities using
+/// XTGETTCAP.
+///
+/// A primary device attributes (DA1) query is sent right after it. Every
+/// terminal answers that one, so once its response arrives it's known that
+/// there won't be an XTGETTCAP answer anymore. Returns `None` if there is
+/// no terminal or it didn't report any of the capabilities within
+/// `timeout`.
+#[cfg(target_family = "unix")]
+pub fn query_color_depth(timeout: Duration) -> Option<ColorDepth> {
+    // "RGB", "Tc" and "colors", hex encoded. They are asked for one by one
+    // because some terminals stop at the first one they don't know.
+    let response = query_tty("\x1BP+q524742\x1B\\\x1BP+q5463\x1B\\\x1BP+q636f6c6f7273\x1B\\\x1B[c", timeout, |response| {
+        find_da1_response(response).is_some()
+    })?;
+
+    let da1 = find_da1_response(&response)?;
+    color_depth_from_tcap(&response[..da1])
+}
+
+#[cfg(not(target_family = "unix"))]
+#[inline]
+pub fn query_color_depth(_timeout: Duration) -> Option<ColorDepth> {
+    None
+}
+
+/// Color depth reported by the XTGETTCAP answers in `data`. Each known
+/// capability is answered with `DCS 1 + r name=value ST`, with name and
+/// value hex encoded, unknown ones with `DCS 0 + r name ST`.
+#[cfg(target_family = "unix")]
+fn color_depth_from_tcap(data: &[u8]) -> Option<ColorDepth> {
+    let mut color_depth = None;
+    let mut offset = 0;
+    while let Some(index) = find(&data[offset..], b"\x1BP1+r") {
+        let start = offset + index + 5;
+        let end = start + find(&data[start..], b"\x1B\\")?;
+        for capability in data[start..end].split(|&byte| byte == b';') {
+            let mut parts = capability.splitn(2, |&byte| byte == b'=');
+            let name = parts.next().and_then(hex_decode);
+            let value = parts.next().and_then(hex_decode);
+            match name.as_deref() {
+                Some("RGB" | "Tc") => return Some(ColorDepth::TrueColor),
+                Some("colors") => {
+                    if let Some(colors) = value.and_then(|value| value.parse().ok()) {
+                        color_depth = Some(color_depth_from_count(colors));
+                    }
+                }
+                _ => {}
+            }
+        }
+        offset = end;
+    }
+    color_depth
+}
+
+#[cfg(target_family = "unix")]
+fn hex_decode(hex: &[u8]) -> Option<String> {
+    let bytes = hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    String::from_utf8(bytes).ok()
+}
+
+/// Writes `query` to the controlling terminal and collects the answer until
+/// `is_complete` says it's done or `timeout` elapsed. Returns `None` if
+/// there is no terminal or the answer was incomplete.
+#[cfg(target_family = "unix")]
+pub fn query_tty(query: &str, timeout: Duration, is_complete: impl Fn(&[u8]) -> bool) -> Option<Vec<u8>> {
+    use std::time::Instant;
+
+    if unsafe { libc::isatty(libc::STDOUT_FILENO) } == 0 {
+        return None;
+    }
+
+    let fd = unsafe { libc::open(c"/dev/tty".as_ptr(), libc::O_RDWR | libc::O_NOCTTY) };
+    if fd < 0 {
+        return None;
+    }
+
+    let result = (|| {
+        let orig_term = get_termios(fd)?;
+        let mut term = orig_term;
+        term.c_lflag &= !(libc::ICANON | libc::ECHO);
+        term.c_cc[libc::VMIN] = 0;
+        term.c_cc[libc::VTIME] = 0;
+        if !set_termios(fd, &term) {
+            return None;
+        }
+
+        let mut response = Vec::new();
+        let mut complete = false;
+        let written = unsafe { libc::write(fd, query.as_ptr().cast(), query.len()) };
+        if written == query.len() as isize {
+            let deadline = Instant::now() + timeout;
+            let mut buf = [0u8; 256];
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+                let millis = (deadline - now).as_millis().max(1) as libc::c_int;
+                if unsafe { libc::poll(&mut pollfd, 1, millis) } <= 0 {
+                    break;
+                }
+                let count = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+                if count <= 0 {
+                    break;
+                }
+                response.extend_from_slice(&buf[..count as usize]);
+                if is_complete(&response) {
+                    complete = true;
+                    break;
+                }
+            }
+        }
+
+        set_termios(fd, &orig_term);
+
+        if complete { Some(response) } else { None }
+    })();
+
+    unsafe { libc::close(fd); }
+
+    result
+}
+
+/// Position of a `CSI ? ... c` (DA1) response in `data`.
+#[cfg(target_family = "unix")]
+fn find_da1_response(data: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(index) = find(&data[offset..], b"\x1B[?") {
+        let start = offset + index;
+        let params = &data[start + 3..];
+        let end = params.iter().position(|&byte| !(byte.is_ascii_digit() || byte == b';'))?;
+        if params[end] == b'c' {
+            return Some(start);
+        }
+        offset = start + 3;
+    }
+    None
+}
+
+#[cfg(target_family = "unix")]
+#[inline]
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+#[cfg(all(test, target_family = "unix"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tcap_true_color() {
+        assert_eq!(color_depth_from_tcap(b"\x1BP1+r524742=382F382F38\x1B\\\x1BP0+r5463\x1B\\"), Some(ColorDepth::TrueColor));
+        assert_eq!(color_depth_from_tcap(b"\x1BP0+r524742\x1B\\\x1BP1+r5463\x1B\\"), Some(ColorDepth::TrueColor));
+        assert_eq!(color_depth_from_tcap(b"\x1BP1+r5463;636f6c6f7273=3136\x1B\\"), Some(ColorDepth::TrueColor));
+    }
+
+    #[test]
+    fn tcap_colors() {
+        assert_eq!(color_depth_from_tcap(b"\x1BP0+r524742\x1B\\\x1BP0+r5463\x1B\\\x1BP1+r636f6c6f7273=323536\x1B\\"), Some(ColorDepth::Xterm256));
+        assert_eq!(color_depth_from_tcap(b"\x1BP1+r636F6C6F7273=3136\x1B\\"), Some(ColorDepth::Ansi16));
+        assert_eq!(color_depth_from_tcap(b"\x1BP1+r636f6c6f7273=38\x1B\\"), Some(ColorDepth::Ansi8));
+    }
+
+    #[test]
+    fn tcap_unknown() {
+        assert_eq!(color_depth_from_tcap(b""), None);
+        assert_eq!(color_depth_from_tcap(b"\x1BP0+r524742\x1B\\\x1BP0+r5463\x1B\\\x1BP0+r636f6c6f7273\x1B\\"), None);
+        assert_eq!(color_depth_from_tcap(b"\x1BP1+r636f6c6f7273=zz\x1B\\"), None);
+    }
+}
@@ -0,0 +1,487 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Legacy terminfo format with 16-bit numbers.
+const MAGIC_16BIT: u16 = 0o432;
+/// Extended number format (ncurses 6.1+) with 32-bit numbers.
+const MAGIC_32BIT: u16 = 0o1036;
+
+/// Index of `colors` (`max_colors`) in the standard number capabilities.
+const NUM_MAX_COLORS: usize = 13;
+
+const DEFAULT_DIRS: &[&str] = &[
+    "/etc/terminfo",
+    "/lib/terminfo",
+    "/usr/share/terminfo",
+    "/usr/lib/terminfo",
+    "/usr/share/lib/terminfo",
+];
+
+/// The parts of a compiled terminfo entry needed to figure out what colors
+/// a terminal supports.
+#[derive(Debug, Default, Clone)]
+pub struct Terminfo {
+    numbers: Vec<i32>,
+    ext_bools: HashMap<String, bool>,
+    ext_numbers: HashMap<String, i32>,
+    ext_strings: HashMap<String, String>,
+}
+
+impl Terminfo {
+    /// Looks up the entry of `term` in the usual terminfo directories.
+    pub fn load(term: &str) -> Option<Self> {
+        let first = term.chars().next()?;
+        if term.contains('/') {
+            return None;
+        }
+
+        for dir in search_dirs() {
+            let mut path = dir.join(first.to_string());
+            path.push(term);
+            if let Some(terminfo) = Self::load_file(&path) {
+                return Some(terminfo);
+            }
+
+            // macOS uses the hex code of the first character.
+            let mut path = dir.join(format!("{:x}", first as u32));
+            path.push(term);
+            if let Some(terminfo) = Self::load_file(&path) {
+                return Some(terminfo);
+            }
+        }
+
+        None
+    }
+
+    #[inline]
+    pub fn load_file(path: &Path) -> Option<Self> {
+        Self::parse(&std::fs::read(path).ok()?)
+    }
+
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader { data, pos: 0 };
+
+        let magic = reader.u16()?;
+        let number_size = match magic {
+            MAGIC_16BIT => 2,
+            MAGIC_32BIT => 4,
+            _ => return None,
+        };
+
+        let names_size     = reader.count()?;
+        let bool_count     = reader.count()?;
+        let num_count      = reader.count()?;
+        let str_count      = reader.count()?;
+        let str_table_size = reader.count()?;
+
+        reader.skip(names_size + bool_count)?;
+        reader.align();
+
+        let mut numbers = Vec::with_capacity(num_count);
+        for _ in 0..num_count {
+            numbers.push(reader.number(number_size)?);
+        }
+
+        reader.skip(str_count * 2 + str_table_size)?;
+        reader.align();
+
+        let mut terminfo = Terminfo { numbers, ..Default::default() };
+
+        // Extended (user defined) capabilities like Tc and RGB. Old entries
+        // simply end here.
+        if reader.remaining() > 0 {
+            terminfo.parse_extended(&mut reader, number_size);
+        }
+
+        Some(terminfo)
+    }
+
+    fn parse_extended(&mut self, reader: &mut Reader, number_size: usize) -> Option<()> {
+        let bool_count     = reader.count()?;
+        let num_count      = reader.count()?;
+        let str_count      = reader.count()?;
+        let _table_entries = reader.count()?;
+        let table_size     = reader.count()?;
+
+        let bools = reader.bytes(bool_count)?.to_vec();
+        reader.align();
+
+        let mut numbers = Vec::with_capacity(num_count);
+        for _ in 0..num_count {
+            numbers.push(reader.number(number_size)?);
+        }
+
+        let mut str_offsets = Vec::with_capacity(str_count);
+        for _ in 0..str_count {
+            str_offsets.push(reader.u16()? as i16);
+        }
+
+        let name_count = bool_count + num_count + str_count;
+        let mut name_offsets = Vec::with_capacity(name_count);
+        for _ in 0..name_count {
+            name_offsets.push(reader.u16()? as i16);
+        }
+
+        let table = reader.bytes(table_size)?;
+
+        // The names follow directly after the last string value.
+        let mut names_start = 0;
+        for &offset in &str_offsets {
+            if offset >= 0 {
+                let value = c_str(table, offset as usize)?;
+                names_start = names_start.max(offset as usize + value.len() + 1);
+            }
+        }
+        let names_table = table.get(names_start..)?;
+
+        let mut names = name_offsets.iter().map(|&offset| {
+            if offset < 0 {
+                return None;
+            }
+            let name = c_str(names_table, offset as usize)?;
+            Some(String::from_utf8_lossy(name).into_owned())
+        });
+
+        for value in bools {
+            if let Some(name) = names.next()? {
+                self.ext_bools.insert(name, value != 0);
+            }
+        }
+
+        for value in numbers {
+            if let Some(name) = names.next()? {
+                self.ext_numbers.insert(name, value);
+            }
+        }
+
+        for offset in str_offsets {
+            if let Some(name) = names.next()? {
+                if offset >= 0 {
+                    if let Some(value) = c_str(table, offset as usize) {
+                        self.ext_strings.insert(name, String::from_utf8_lossy(value).into_owned());
+                    }
+                }
+            }
+        }
+
+        Some(())
+    }
+
+    /// Number of colors (`colors` capability), if defined.
+    #[inline]
+    pub fn max_colors(&self) -> Option<i32> {
+        self.numbers.get(NUM_MAX_COLORS).copied().filter(|&colors| colors >= 0)
+    }
+
+    /// Whether the entry advertises direct (24-bit) color support via the
+    /// `Tc` or `RGB` extended capabilities.
+    pub fn has_true_color(&self) -> bool {
+        self.ext_bools.get("Tc").copied().unwrap_or(false) ||
+        self.ext_bools.get("RGB").copied().unwrap_or(false) ||
+        self.ext_numbers.contains_key("RGB") ||
+        self.ext_strings.contains_key("RGB")
+    }
+}
+
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(dir) = std::env::var_os("TERMINFO") {
+        dirs.push(PathBuf::from(dir));
+    }
+
+    if let Some(home) = std::env::var_os("HOME") {
+        dirs.push(PathBuf::from(home).join(".terminfo"));
+    }
+
+    let mut defaults_added = false;
+    if let Some(value) = std::env::var_os("TERMINFO_DIRS") {
+        for dir in std::env::split_paths(&value) {
+            if dir.as_os_str().is_empty() {
+                if !defaults_added {
+                    dirs.extend(DEFAULT_DIRS.iter().map(PathBuf::from));
+                    defaults_added = true;
+                }
+            } else {
+                dirs.push(dir);
+            }
+        }
+    }
+
+    if !defaults_added {
+        dirs.extend(DEFAULT_DIRS.iter().map(PathBuf::from));
+    }
+
+    dirs
+}
+
+fn c_str(table: &[u8], offset: usize) -> Option<&[u8]> {
+    let rest = table.get(offset..)?;
+    let len = rest.iter().position(|&byte| byte == 0)?;
+    Some(&rest[..len])
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    #[inline]
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    #[inline]
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    #[inline]
+    fn skip(&mut self, len: usize) -> Option<()> {
+        self.bytes(len).map(|_| ())
+    }
+
+    #[inline]
+    fn align(&mut self) {
+        if self.pos % 2 != 0 && self.pos < self.data.len() {
+            self.pos += 1;
+        }
+    }
+
+    #[inline]
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.bytes(2)?;
+        Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// A size field. Negative values mean "absent", which is the same as 0.
+    #[inline]
+    fn count(&mut self) -> Option<usize> {
+        let value = self.u16()? as i16;
+        Some(value.max(0) as usize)
+    }
+
+    #[inline]
+    fn number(&mut self, size: usize) -> Option<i32> {
+        let bytes = self.bytes(size)?;
+        if size == 4 {
+            Some(i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        } else {
+            Some(i16::from_le_bytes([bytes[0], bytes[1]]) as i32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Extended capabilities of a test entry. A string of `None` is absent.
+    #[derive(Default)]
+    struct Extended<'a> {
+        bools: &'a [(&'a str, bool)],
+        numbers: &'a [(&'a str, i32)],
+        strings: &'a [(&'a str, Option<&'a str>)],
+    }
+
+    fn push_u16(data: &mut Vec<u8>, value: i32) {
+        data.extend_from_slice(&(value as i16).to_le_bytes());
+    }
+
+    fn push_number(data: &mut Vec<u8>, magic: u16, value: i32) {
+        if magic == MAGIC_32BIT {
+            data.extend_from_slice(&value.to_le_bytes());
+        } else {
+            push_u16(data, value);
+        }
+    }
+
+    fn align(data: &mut Vec<u8>) {
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+    }
+
+    /// Compiles a terminfo entry with the given standard numbers, one
+    /// boolean, one string and optionally extended capabilities.
+    fn entry(magic: u16, numbers: &[i32], extended: Option<Extended>) -> Vec<u8> {
+        let names = b"test|Test terminal\0";
+        let strings = b"\x1B[H\0";
+
+        let mut data = Vec::new();
+        push_u16(&mut data, magic as i32);
+        push_u16(&mut data, names.len() as i32);
+        push_u16(&mut data, 1);
+        push_u16(&mut data, numbers.len() as i32);
+        push_u16(&mut data, 1);
+        push_u16(&mut data, strings.len() as i32);
+        data.extend_from_slice(names);
+        data.push(1);
+        align(&mut data);
+        for &number in numbers {
+            push_number(&mut data, magic, number);
+        }
+        push_u16(&mut data, 0);
+        data.extend_from_slice(strings);
+        align(&mut data);
+
+        let Some(extended) = extended else {
+            return data;
+        };
+
+        let mut table = Vec::new();
+        let mut str_offsets = Vec::new();
+        for &(_, value) in extended.strings {
+            match value {
+                Some(value) => {
+                    str_offsets.push(table.len() as i32);
+                    table.extend_from_slice(value.as_bytes());
+                    table.push(0);
+                }
+                None => str_offsets.push(-1),
+            }
+        }
+
+        let names = extended.bools.iter().map(|&(name, _)| name)
+            .chain(extended.numbers.iter().map(|&(name, _)| name))
+            .chain(extended.strings.iter().map(|&(name, _)| name));
+        let mut name_table = Vec::new();
+        let mut name_offsets = Vec::new();
+        for name in names {
+            name_offsets.push(name_table.len() as i32);
+            name_table.extend_from_slice(name.as_bytes());
+            name_table.push(0);
+        }
+        table.extend_from_slice(&name_table);
+
+        push_u16(&mut data, extended.bools.len() as i32);
+        push_u16(&mut data, extended.numbers.len() as i32);
+        push_u16(&mut data, extended.strings.len() as i32);
+        push_u16(&mut data, (str_offsets.len() + name_offsets.len()) as i32);
+        push_u16(&mut data, table.len() as i32);
+        for &(_, value) in extended.bools {
+            data.push(value as u8);
+        }
+        align(&mut data);
+        for &(_, value) in extended.numbers {
+            push_number(&mut data, magic, value);
+        }
+        for offset in str_offsets.into_iter().chain(name_offsets) {
+            push_u16(&mut data, offset);
+        }
+        data.extend_from_slice(&table);
+        data
+    }
+
+    fn colors(count: i32) -> Vec<i32> {
+        let mut numbers = vec![-1; NUM_MAX_COLORS + 1];
+        numbers[NUM_MAX_COLORS] = count;
+        numbers
+    }
+
+    #[test]
+    fn parses_legacy_format() {
+        let terminfo = Terminfo::parse(&entry(MAGIC_16BIT, &colors(256), None)).unwrap();
+        assert_eq!(terminfo.max_colors(), Some(256));
+        assert!(!terminfo.has_true_color());
+    }
+
+    #[test]
+    fn parses_32bit_number_format() {
+        let terminfo = Terminfo::parse(&entry(MAGIC_32BIT, &colors(0x1000000), None)).unwrap();
+        assert_eq!(terminfo.max_colors(), Some(0x1000000));
+    }
+
+    #[test]
+    fn absent_number_is_none() {
+        let terminfo = Terminfo::parse(&entry(MAGIC_16BIT, &colors(-1), None)).unwrap();
+        assert_eq!(terminfo.max_colors(), None);
+
+        let terminfo = Terminfo::parse(&entry(MAGIC_16BIT, &[8, 80], None)).unwrap();
+        assert_eq!(terminfo.max_colors(), None);
+    }
+
+    #[test]
+    fn parses_extended_capabilities() {
+        for magic in [MAGIC_16BIT, MAGIC_32BIT] {
+            let extended = Extended {
+                bools: &[("AX", true), ("Tc", true)],
+                numbers: &[("U8", 1)],
+                strings: &[("Ss", Some("\x1B[%p1%d q"))],
+            };
+            let terminfo = Terminfo::parse(&entry(magic, &colors(256), Some(extended))).unwrap();
+            assert_eq!(terminfo.max_colors(), Some(256));
+            assert_eq!(terminfo.ext_bools.get("Tc"), Some(&true));
+            assert_eq!(terminfo.ext_numbers.get("U8"), Some(&1));
+            assert_eq!(terminfo.ext_strings.get("Ss").map(String::as_str), Some("\x1B[%p1%d q"));
+            assert!(terminfo.has_true_color());
+        }
+    }
+
+    #[test]
+    fn absent_string_capability() {
+        let extended = Extended {
+            strings: &[("RGB", None), ("Ss", Some("x"))],
+            ..Default::default()
+        };
+        let terminfo = Terminfo::parse(&entry(MAGIC_16BIT, &colors(256), Some(extended))).unwrap();
+        assert!(!terminfo.ext_strings.contains_key("RGB"));
+        assert_eq!(terminfo.ext_strings.get("Ss").map(String::as_str), Some("x"));
+        assert!(!terminfo.has_true_color());
+
+        let extended = Extended {
+            strings: &[("RGB", Some(""))],
+            ..Default::default()
+        };
+        let terminfo = Terminfo::parse(&entry(MAGIC_16BIT, &colors(256), Some(extended))).unwrap();
+        assert!(terminfo.has_true_color());
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let mut data = entry(MAGIC_16BIT, &colors(256), None);
+        data[0] ^= 0xFF;
+        assert!(Terminfo::parse(&data).is_none());
+        assert!(Terminfo::parse(&[]).is_none());
+        assert!(Terminfo::parse(&[0x1A]).is_none());
+    }
+
+    #[test]
+    fn rejects_truncated_header_and_sections() {
+        let standard = entry(MAGIC_16BIT, &colors(256), None);
+        for len in 0..standard.len() {
+            assert!(Terminfo::parse(&standard[..len]).is_none(), "prefix of {len} bytes");
+        }
+    }
+
+    #[test]
+    fn truncated_extended_section_does_not_panic() {
+        let extended = Extended {
+            bools: &[("Tc", true)],
+            numbers: &[("U8", 1)],
+            strings: &[("Ss", Some("abc")), ("RGB", None)],
+        };
+        let data = entry(MAGIC_32BIT, &colors(256), Some(extended));
+        for len in 0..data.len() {
+            if let Some(terminfo) = Terminfo::parse(&data[..len]) {
+                assert_eq!(terminfo.max_colors(), Some(256));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_counts() {
+        let mut data = entry(MAGIC_16BIT, &colors(256), None);
+        // Number of numbers far beyond the end of the data.
+        data[6..8].copy_from_slice(&0x7FFFu16.to_le_bytes());
+        assert!(Terminfo::parse(&data).is_none());
+
+        // Negative sizes mean absent and leave nothing to read.
+        let mut data = entry(MAGIC_16BIT, &colors(256), None);
+        data[6..8].copy_from_slice(&(-1i16).to_le_bytes());
+        assert!(Terminfo::parse(&data).is_some_and(|terminfo| terminfo.max_colors().is_none()));
+    }
+}
@@ -0,0 +1,69 @@
+use image::{Rgb, Rgba, RgbaImage};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::Color;
+use ratatui::widgets::Widget;
+
+use crate::color_depth::ColorDepth;
+use crate::palette::Palette;
+use crate::renderer::Renderer;
+
+/// Shows an image in a ratatui application.
+///
+/// The image is painted onto a canvas the size of the widget's area using
+/// the renderer's style, filter, background, color depth and encoder, and
+/// the resulting cells are written straight into the [`Buffer`]. ratatui
+/// takes care of only redrawing what changed. The renderer's canvas size
+/// is ignored.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageWidget<'a> {
+    image: &'a RgbaImage,
+    renderer: &'a Renderer,
+}
+
+impl<'a> ImageWidget<'a> {
+    #[inline]
+    pub fn new(image: &'a RgbaImage, renderer: &'a Renderer) -> Self {
+        Self { image, renderer }
+    }
+}
+
+impl Widget for ImageWidget<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let area = area.intersection(buf.area);
+        if area.is_empty() {
+            return;
+        }
+
+        let color_depth = self.renderer.options().color_depth;
+        self.renderer.render_cells(self.image, area.width as u32, area.height as u32, |x, y, cell| {
+            let target = &mut buf[(area.x + x as u16, area.y + y as u16)];
+            target.set_char(cell.ch);
+            target.set_fg(if cell.needs_fg() { to_color(cell.fg, color_depth) } else { Color::Reset });
+            target.set_bg(if cell.needs_bg() { to_color(cell.bg, color_depth) } else { Color::Reset });
+        });
+    }
+}
+
+/// Named colors in the order of the ANSI color indices.
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black, Color::Red, Color::Green, Color::Yellow,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::Gray,
+    Color::DarkGray, Color::LightRed, Color::LightGreen, Color::LightYellow,
+    Color::LightBlue, Color::LightMagenta, Color::LightCyan, Color::White,
+];
+
+/// The ratatui color that gives the same output as the escape sequences of
+/// the encoders at `color_depth`. `None` is the terminal's default color.
+fn to_color(color: Option<Rgba<u8>>, color_depth: ColorDepth) -> Color {
+    let Some(Rgba([r, g, b, _])) = color else {
+        return Color::Reset;
+    };
+    let index = || Palette::for_color_depth(color_depth).map_or(0, |palette| palette.terminal_index(Rgb([r, g, b])));
+    match color_depth {
+        ColorDepth::TrueColor | ColorDepth::Auto => Color::Rgb(r, g, b),
+        ColorDepth::Xterm256 => Color::Indexed(index()),
+        ColorDepth::Ansi16 | ColorDepth::Ansi8 => ANSI_COLORS[index() as usize & 0xF],
+        ColorDepth::Monochrome => Color::Reset,
+    }
+}